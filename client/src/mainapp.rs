@@ -1,13 +1,17 @@
 use crate::{
     asset::Assets,
     basesys::{App, BaseSys},
-    net::PollingHttp,
+    net::{self, PollingHttp, PushSocket},
     testmode::{self, TestMode},
 };
 use anyhow::{bail, Result};
 use game::{jsif, mjsys};
 use getopts::{Matches, Options};
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    rc::Rc,
+};
 use web_sys::{CanvasRenderingContext2d, HtmlImageElement};
 
 // On server build, PUBLIC_URL will be specified by env
@@ -31,6 +35,10 @@ fn version() -> String {
 
 const CANVAS_W: u32 = 1280;
 const CANVAS_H: u32 = 720;
+// view polling interval without WebSocket
+const POLL_FRAMES: u64 = 60;
+// recent chat lines on the screen
+const CHAT_LINES: usize = 8;
 
 type DbgCmdFunc = dyn Fn(&mut MainApp, &Options, Matches) -> Result<()>;
 struct DbgCmd {
@@ -82,6 +90,14 @@ struct MainApp {
 
     assets: Assets,
     http: PollingHttp,
+    // game view updates by WebSocket, or polling view_url as a fallback
+    push: Option<PushSocket>,
+    view_url: Option<String>,
+    // seat chat by `game`, spectator chat by `chat -r`
+    chat_url: Option<String>,
+    chat: Rc<RefCell<VecDeque<String>>>,
+    // by `account -l` for `room -j` and `queue`
+    session: Rc<RefCell<Option<String>>>,
     frame: u64,
     fps: f64,
     fps_start: f64,
@@ -136,6 +152,11 @@ impl MainApp {
 
             assets,
             http,
+            push: None,
+            view_url: None,
+            chat_url: None,
+            chat: Default::default(),
+            session: Default::default(),
             frame: 0,
             fps: 0.0,
             fps_start: 0.0,
@@ -174,6 +195,45 @@ impl MainApp {
 
         *self.state.borrow_mut() = State::SelectRoom(None);
     }
+
+    fn get_view(&mut self, url: &str) {
+        let state = Rc::clone(&self.state);
+        self.http.get(url, move |result| {
+            log::debug!("{:?}", result);
+            if let Ok(json) = result {
+                if let Ok(view) = serde_json::from_str::<jsif::LocalView>(json) {
+                    log::debug!("{:?}", view);
+                    *state.borrow_mut() = State::Main(Some(Box::new(view)));
+                }
+            }
+        });
+    }
+
+    fn on_push(state: &Rc<RefCell<State>>, chat: &Rc<RefCell<VecDeque<String>>>, text: &str) {
+        match serde_json::from_str::<jsif::PushMessage>(text) {
+            Ok(jsif::PushMessage::View(view)) => {
+                log::debug!("{:?}", view);
+                *state.borrow_mut() = State::Main(Some(view));
+            }
+            Ok(jsif::PushMessage::Error(err)) => log::warn!("{}", err.message),
+            Ok(jsif::PushMessage::Chat(e)) => {
+                if let jsif::GameEvent::Chat { player, body } = e.event {
+                    let from = match player {
+                        Some(p) => format!("Player{p}"),
+                        None => "Spectator".to_string(),
+                    };
+                    let line = format!("{from}: {}", body.text());
+                    log::info!("{line}");
+                    let mut chat = chat.borrow_mut();
+                    chat.push_back(line);
+                    while chat.len() > CHAT_LINES {
+                        chat.pop_front();
+                    }
+                }
+            }
+            Err(err) => log::error!("{err}"),
+        }
+    }
 }
 
 impl App for MainApp {
@@ -197,6 +257,19 @@ impl App for MainApp {
 
         // poll network
         self.http.poll();
+        if let Some(push) = &mut self.push {
+            let state = Rc::clone(&self.state);
+            let chat = Rc::clone(&self.chat);
+            push.poll(|text| Self::on_push(&state, &chat, text));
+            if push.is_closed() {
+                log::warn!("WebSocket closed, fall back to polling");
+                self.push = None;
+            }
+        } else if let Some(url) = self.view_url.clone() {
+            if self.frame.is_multiple_of(POLL_FRAMES) && self.http.rest() == 0 {
+                self.get_view(&url);
+            }
+        }
     }
 
     fn render(&mut self, context: &CanvasRenderingContext2d, width: u32, height: u32) {
@@ -313,6 +386,14 @@ impl MainApp {
                 .unwrap();
             x += w;
         }
+
+        context.set_fill_style(&"white".to_string().into());
+        context.set_font("16px sans-serif");
+        for (i, line) in self.chat.borrow().iter().enumerate() {
+            context
+                .fill_text(line, 900.0, 450.0 + 20.0 * i as f64)
+                .unwrap();
+        }
     }
 }
 
@@ -372,14 +453,39 @@ impl MainApp {
         let mut opts = Options::new();
         opts.optflag("h", "help", "Print help");
         opts.optopt("c", "create", "Create a room", "ROOM_COMMENT");
+        opts.optopt("j", "join", "Join a room", "ROOM_ID");
         Self::insert_dbg_cmd(&mut dbg_cmds, "room", opts, Self::dbg_room);
 
         let mut opts = Options::new();
         opts.optflag("h", "help", "Print help");
         opts.optopt("r", "room", "Room ID", "ROOM_ID");
         opts.optopt("p", "player", "Create a room", "PLAYER#");
+        opts.optopt("t", "token", "Seat token", "TOKEN");
+        opts.optflag("w", "websocket", "Use WebSocket");
+        opts.optopt("a", "action", "Send an action", "ACTION_JSON");
         Self::insert_dbg_cmd(&mut dbg_cmds, "game", opts, Self::dbg_game);
 
+        let mut opts = Options::new();
+        opts.optflag("h", "help", "Print help");
+        opts.optflag("l", "list", "List stamps");
+        opts.optopt("s", "stamp", "Send a stamp", "STAMP");
+        opts.optopt("r", "room", "Room ID (as a spectator)", "ROOM_ID");
+        Self::insert_dbg_cmd(&mut dbg_cmds, "chat", opts, Self::dbg_chat);
+
+        let mut opts = Options::new();
+        opts.optflag("h", "help", "Print help");
+        opts.optopt("t", "ticket", "Queue status", "TICKET");
+        opts.optopt("c", "cancel", "Leave the queue", "TICKET");
+        Self::insert_dbg_cmd(&mut dbg_cmds, "queue", opts, Self::dbg_queue);
+
+        let mut opts = Options::new();
+        opts.optflag("h", "help", "Print help");
+        opts.optflag("r", "register", "Create an account");
+        opts.optflag("l", "login", "Login for room -j and queue");
+        opts.optflag("H", "history", "Rating history");
+        opts.optflag("s", "stats", "Player statistics");
+        Self::insert_dbg_cmd(&mut dbg_cmds, "account", opts, Self::dbg_account);
+
         dbg_cmds
     }
 
@@ -463,15 +569,29 @@ impl MainApp {
             let url = format!("{}/api/room", apiroot());
             let param = jsif::CreateRoom {
                 comment: comment.clone(),
+                rule: Default::default(),
             };
             self.http.post(&url, &param, |result| {
                 log::debug!("{:?}", result);
                 if let Ok(json) = result {
-                    if let Ok(room) = serde_json::from_str::<jsif::Room>(json) {
+                    if let Ok(room) = serde_json::from_str::<jsif::CreateRoomResult>(json) {
                         log::debug!("Room created successfully\n{:?}", room);
                     }
                 }
             });
+        } else if let Some(room) = args.opt_str("j") {
+            let url = match self.session.borrow().as_ref() {
+                Some(session) => format!("{}/api/room/{room}/join?session={session}", apiroot()),
+                None => format!("{}/api/room/{room}/join", apiroot()),
+            };
+            self.http.request(&url, "POST", "", |result| {
+                log::debug!("{:?}", result);
+                if let Ok(json) = result {
+                    if let Ok(seat) = serde_json::from_str::<jsif::SeatToken>(json) {
+                        log::debug!("Joined successfully\n{:?}", seat);
+                    }
+                }
+            });
         } else {
             let url = format!("{}/api/room", apiroot());
             self.http.get(&url, |result| {
@@ -496,16 +616,177 @@ impl MainApp {
 
         let room = args.opt_str("r").unwrap_or("0".to_string());
         let player = args.opt_str("p").unwrap_or("0".to_string());
-        let url = format!("{}/api/room/{room}/{player}", apiroot());
-        let state = Rc::clone(&self.state);
-        self.http.get(&url, move |result| {
-            log::debug!("{:?}", result);
-            if let Ok(json) = result {
-                if let Ok(view) = serde_json::from_str::<jsif::LocalView>(json) {
-                    log::debug!("{:?}", view);
-                    *state.borrow_mut() = State::Main(Some(Box::new(view)));
+        let token = args.opt_str("t").unwrap_or_default();
+        let url = format!("{}/api/room/{room}/{player}?token={token}", apiroot());
+        self.chat_url = Some(format!(
+            "{}/api/room/{room}/{player}/chat?token={token}",
+            apiroot()
+        ));
+
+        if let Some(action) = args.opt_str("a") {
+            let action: jsif::Action = serde_json::from_str(&action)?;
+            match &self.push {
+                Some(push) => push.send(&action)?,
+                None => self.http.post(&url, &action, |result| {
+                    log::debug!("{:?}", result);
+                }),
+            }
+            return Ok(());
+        }
+
+        if args.opt_present("w") {
+            let ws_url = format!(
+                "{}{}/api/room/{room}/{player}/ws?token={token}",
+                net::ws_origin(),
+                apiroot()
+            );
+            self.push = Some(PushSocket::new(&ws_url)?);
+        } else {
+            self.push = None;
+            self.get_view(&url);
+        }
+        self.view_url = Some(url);
+
+        Ok(())
+    }
+
+    fn dbg_queue(&mut self, opts: &Options, args: Matches) -> Result<()> {
+        if args.opt_present("h") {
+            let brief = "Matchmaking queue.\nqueue [options] [PRESET]";
+            log::debug!("{}", opts.usage(brief));
+            return Ok(());
+        }
+
+        if let Some(ticket) = args.opt_str("t") {
+            let url = format!("{}/api/queue/{ticket}", apiroot());
+            self.http.get(&url, |result| {
+                log::debug!("{:?}", result);
+                if let Ok(json) = result {
+                    if let Ok(jsif::QueueStatus::Matched { room, seat }) =
+                        serde_json::from_str::<jsif::QueueStatus>(json)
+                    {
+                        log::debug!("game -r {room} -p {} -t {}", seat.player, seat.token);
+                    }
+                }
+            });
+        } else if let Some(ticket) = args.opt_str("c") {
+            let url = format!("{}/api/queue/{ticket}", apiroot());
+            self.http.request(&url, "DELETE", "", |result| {
+                log::debug!("{:?}", result);
+            });
+        } else if let Some(preset) = args.free.first() {
+            let url = format!("{}/api/queue", apiroot());
+            let param = jsif::EnterQueue {
+                preset: preset.clone(),
+                session: self.session.borrow().clone(),
+            };
+            self.http.post(&url, &param, |result| {
+                log::debug!("{:?}", result);
+                if let Ok(json) = result {
+                    if let Ok(ticket) = serde_json::from_str::<jsif::QueueTicket>(json) {
+                        log::debug!("queue -t {}", ticket.ticket);
+                    }
                 }
+            });
+        } else {
+            let url = format!("{}/api/queue", apiroot());
+            self.http.get(&url, |result| {
+                log::debug!("{:?}", result);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn dbg_account(&mut self, opts: &Options, args: Matches) -> Result<()> {
+        if args.opt_present("h") {
+            let brief = "Account API.\naccount [options] [NAME [PASSWORD]]";
+            log::debug!("{}", opts.usage(brief));
+            return Ok(());
+        }
+
+        let name = args.free.first();
+        let password = args.free.get(1);
+        if args.opt_present("r") || args.opt_present("l") {
+            let (Some(name), Some(password)) = (name, password) else {
+                bail!("NAME and PASSWORD required");
+            };
+            let param = jsif::Credentials {
+                name: name.clone(),
+                password: password.clone(),
+            };
+            if args.opt_present("r") {
+                let url = format!("{}/api/account", apiroot());
+                self.http.post(&url, &param, |result| {
+                    log::debug!("{:?}", result);
+                });
+            } else {
+                let url = format!("{}/api/account/login", apiroot());
+                let session = Rc::clone(&self.session);
+                self.http.post(&url, &param, move |result| {
+                    log::debug!("{:?}", result);
+                    if let Ok(json) = result {
+                        if let Ok(s) = serde_json::from_str::<jsif::Session>(json) {
+                            log::debug!("Logged in as {}", s.name);
+                            *session.borrow_mut() = Some(s.session);
+                        }
+                    }
+                });
+            }
+        } else if let Some(name) = name {
+            let url = if args.opt_present("H") {
+                format!("{}/api/account/{name}/history", apiroot())
+            } else if args.opt_present("s") {
+                format!("{}/api/player/{name}/stats", apiroot())
+            } else {
+                format!("{}/api/account/{name}", apiroot())
+            };
+            self.http.get(&url, |result| {
+                log::debug!("{:?}", result);
+            });
+        } else {
+            let url = format!("{}/api/account", apiroot());
+            self.http.get(&url, |result| {
+                log::debug!("{:?}", result);
+            });
+        }
+
+        Ok(())
+    }
+
+    fn dbg_chat(&mut self, opts: &Options, args: Matches) -> Result<()> {
+        if args.opt_present("h") {
+            let brief = "Chat in the room of `game`.\nchat [options] [TEXT...]";
+            log::debug!("{}", opts.usage(brief));
+            return Ok(());
+        }
+
+        if args.opt_present("l") {
+            for stamp in jsif::Stamp::ALL {
+                log::debug!("{stamp:?}: {}", stamp.text());
             }
+            return Ok(());
+        }
+
+        let url = match (args.opt_str("r"), &self.chat_url) {
+            (Some(room), _) => format!("{}/api/room/{room}/chat", apiroot()),
+            (None, Some(url)) => url.clone(),
+            (None, None) => bail!("No room (game or -r)"),
+        };
+        let body = match args.opt_str("s") {
+            Some(name) => {
+                let stamp = jsif::Stamp::ALL
+                    .into_iter()
+                    .find(|s| format!("{s:?}").eq_ignore_ascii_case(&name));
+                match stamp {
+                    Some(stamp) => jsif::ChatBody::Stamp(stamp),
+                    None => bail!("Invalid stamp: {name}"),
+                }
+            }
+            None => jsif::ChatBody::Text(args.free.join(" ")),
+        };
+        self.http.post(&url, &body, |result| {
+            log::debug!("{:?}", result);
         });
 
         Ok(())
@@ -1,6 +1,12 @@
 use anyhow::{anyhow, Result};
 use serde::Serialize;
-use web_sys::XmlHttpRequest;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::Rc,
+};
+use wasm_bindgen::{prelude::*, JsCast};
+use web_sys::{MessageEvent, WebSocket, XmlHttpRequest};
 
 pub type Handler = Box<dyn FnMut(Result<&str>)>;
 
@@ -99,3 +105,96 @@ impl PollingHttp {
         self.request(url, "POST", &data, callback)
     }
 }
+
+// ws:// or wss:// of the current page
+pub fn ws_origin() -> String {
+    let location = web_sys::window().unwrap().location();
+    let protocol = location.protocol().unwrap();
+    let host = location.host().unwrap();
+    let scheme = if protocol == "https:" { "wss" } else { "ws" };
+
+    format!("{scheme}://{host}")
+}
+
+// WebSocket push channel
+// received text messages are queued and handled by poll() like PollingHttp
+pub struct PushSocket {
+    ws: WebSocket,
+    recv: Rc<RefCell<VecDeque<String>>>,
+    // by error or close
+    closed: Rc<Cell<bool>>,
+    // keep alive while the socket is used
+    _onmessage: Closure<dyn FnMut(MessageEvent)>,
+    _onclose: Closure<dyn FnMut(JsValue)>,
+}
+
+impl PushSocket {
+    pub fn new(url: &str) -> Result<Self> {
+        let ws = WebSocket::new(url).map_err(|e| anyhow!("WebSocket error: {e:?}"))?;
+        let recv: Rc<RefCell<VecDeque<String>>> = Default::default();
+        let closed = Rc::new(Cell::new(false));
+
+        let onmessage = {
+            let recv = Rc::clone(&recv);
+            Closure::<dyn FnMut(_)>::new(move |event: MessageEvent| {
+                if let Some(text) = event.data().as_string() {
+                    recv.borrow_mut().push_back(text);
+                }
+            })
+        };
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        let onclose = {
+            let closed = Rc::clone(&closed);
+            Closure::<dyn FnMut(_)>::new(move |_event: JsValue| {
+                closed.set(true);
+            })
+        };
+        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+        ws.set_onerror(Some(onclose.as_ref().unchecked_ref()));
+
+        Ok(Self {
+            ws,
+            recv,
+            closed,
+            _onmessage: onmessage,
+            _onclose: onclose,
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.get()
+    }
+
+    pub fn poll<F>(&mut self, mut callback: F) -> usize
+    where
+        F: FnMut(&str),
+    {
+        let mut count = 0;
+        while let Some(text) = self.recv.borrow_mut().pop_front() {
+            callback(&text);
+            count += 1;
+        }
+
+        count
+    }
+
+    // error if not connected yet or closed
+    pub fn send<T>(&self, data: &T) -> Result<()>
+    where
+        T: Serialize,
+    {
+        let data = serde_json::to_string(data).unwrap();
+        self.ws
+            .send_with_str(&data)
+            .map_err(|e| anyhow!("WebSocket error: {e:?}"))
+    }
+}
+
+impl Drop for PushSocket {
+    fn drop(&mut self) {
+        self.ws.set_onmessage(None);
+        self.ws.set_onclose(None);
+        self.ws.set_onerror(None);
+        let _ = self.ws.close();
+    }
+}
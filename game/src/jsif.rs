@@ -25,6 +25,12 @@ pub struct ServerInfo {
 pub struct Room {
     pub id: u64,
     pub comment: String,
+    pub rule: RuleConfig,
+    // taken or not for each seat
+    pub seats: Vec<bool>,
+    // account name for each seat (None if a guest or free)
+    #[serde(default)]
+    pub accounts: Vec<Option<String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -33,10 +39,165 @@ pub struct RoomList(pub Vec<Room>);
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateRoom {
     pub comment: String,
+    // default rule if omitted
+    #[serde(default)]
+    pub rule: RuleConfig,
 }
 
-// publish to each player
+// server to client message on the push channel (WebSocket)
+// client to server message is jsif::Action
+#[derive(Debug, Serialize, Deserialize)]
+pub enum PushMessage {
+    // the latest view at every update
+    View(Box<LocalView>),
+    // rejected action
+    Error(ErrorMsg),
+    // GameEvent::Chat after the view
+    Chat(Event),
+}
+
+// POST /api/room result, the token is required to delete the room
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreateRoomResult {
+    pub room: Room,
+    pub owner_token: String,
+}
+
+// join result, the token is required for the seat's view and actions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeatToken {
+    pub player: u32,
+    pub token: String,
+}
+
+// GET /api/queue result item
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuePreset {
+    pub name: String,
+    pub rule: RuleConfig,
+    // players in the queue
+    pub waiting: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueList(pub Vec<QueuePreset>);
+
+// POST /api/queue
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnterQueue {
+    pub preset: String,
+    // login session to play as the account
+    #[serde(default)]
+    pub session: Option<String>,
+}
+
+// POST /api/queue result, poll GET /api/queue/{ticket} until matched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueTicket {
+    pub ticket: String,
+    pub preset: String,
+}
+
+// GET /api/queue/{ticket}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum QueueStatus {
+    // players in the same queue (with self)
+    Waiting { waiting: u32, player_count: u32 },
+    Matched { room: u64, seat: SeatToken },
+}
+
+// POST /api/account (register) and /api/account/login
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Credentials {
+    pub name: String,
+    pub password: String,
+}
+
+// login result, join with ?session=xxx to play as the account
+// expires in 7 days, DELETE /api/account/login?session=xxx to logout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub name: String,
+    pub session: String,
+}
+
+// GET /api/account/{name}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub name: String,
+    pub rating: f64,
+    // rated games
+    pub games: u32,
+}
+
+// GET /api/account result, sorted by rating
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountList(pub Vec<AccountInfo>);
+
+// rating update after a game
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingChange {
+    pub room: u64,
+    // unix time in secs
+    pub time: u64,
+    // 0 origin
+    pub rank: u32,
+    pub player_count: u32,
+    // after the game
+    pub rating: f64,
+    pub delta: f64,
+}
+
+// GET /api/account/{name}/history result, oldest first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingHistory(pub Vec<RatingChange>);
+
+// GET /api/player/{name}/stats from the recorded games
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerStats {
+    pub name: String,
+    pub games: u32,
+    pub rounds: u32,
+    // per round
+    pub win_rate: f64,
+    pub deal_in_rate: f64,
+    pub reach_rate: f64,
+    pub call_rate: f64,
+    // hand value without hon and deposit
+    pub average_win: f64,
+    // 1 origin
+    pub average_rank: f64,
+    // count for each rank
+    pub ranks: Vec<u32>,
+    // (yaku name, win count), most frequent first
+    pub yaku: Vec<(String, u32)>,
+}
+
+// GET /api/admin/room result item
 #[derive(Debug, Serialize, Deserialize)]
+pub struct AdminRoom {
+    pub room: Room,
+    pub phase: GamePhase,
+    // since the last join, leave or action
+    pub idle_secs: u64,
+    // game state update count
+    pub updates: u64,
+}
+
+// red five flag on pai code (e.g. PAI_RED | 5m)
+pub const PAI_RED: i32 = 0x100;
+
+// pai code without the red flag
+pub fn pai_code(pai: i32) -> i32 {
+    pai & !PAI_RED
+}
+
+pub fn is_red(pai: i32) -> bool {
+    pai & PAI_RED != 0
+}
+
+// publish to each player
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocalView {
     pub common: CommonState,
     pub local: LocalState,
@@ -51,46 +212,373 @@ pub struct CommonState {
     // active player index and phase
     pub turn: u32,
     pub phase: GamePhase,
-    // wind 0..round_max, parent 0..player_count, hon
+    // wind 0..round_max (+1 if extended), parent 0..player_count, hon
     pub wind: u32,
     pub parent: u32,
     pub hon: u32,
-    // TODO
-    // dora
+    // reach deposit count on the table
+    pub deposit: u32,
+    // live wall count (without wang pai)
+    pub yama_rest: u32,
+    // dora indicators
+    pub dora: Vec<i32>,
+    // discarded pais for each player
+    pub rivers: Vec<Vec<i32>>,
+    // fulou for each player
+    pub melds: Vec<Vec<Meld>>,
+    // reach declared for each player
+    pub reach: Vec<bool>,
+    // valid only if phase == ShowResult
+    pub result: Option<RoundResult>,
+    // valid only if phase == GameResult
+    pub final_result: Option<FinalResult>,
+    // constant
+    pub rule: RuleConfig,
 }
 
-// player-dependent data view
-#[derive(Debug, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MeldType {
+    Chi,
+    Pon,
+    BlindKan,
+    OpenKan,
+    AddedKan,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Meld {
+    pub mtype: MeldType,
+    // the lowest pai if Chi
+    pub pai: i32,
+    // called from (self if BlindKan)
+    pub from: u32,
+    // red five count in the meld
+    pub red: u32,
+}
+
+// the result of a round (kyoku)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoundResult {
+    pub reason: RoundEnd,
+    // for each player
+    pub tenpai: Vec<bool>,
+    // revealed hands (empty if not revealed)
+    pub hands: Vec<Vec<i32>>,
+    // point diff for each player
+    pub deltas: Vec<i32>,
+    // empty if draw
+    pub wins: Vec<WinResult>,
+    // parent continues
+    pub renchan: bool,
+    // hon for the next round
+    pub hon: u32,
+    // this is the last round
+    pub game_over: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WinResult {
+    pub player: u32,
+    // == player if Tsumo
+    pub from: u32,
+    pub pai: i32,
+    pub fan: u32,
+    pub fu: u32,
+    pub yakuman_count: u32,
+    // mjsys::yaku::Yaku bits
+    pub yaku: u64,
+    // mjsys::yaku::Yakuman bits
+    pub yakuman: u32,
+    pub yaku_str: Vec<String>,
+    // hand value (without hon and deposit)
+    pub point: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoundEnd {
+    Tsumo,
+    Ron,
+    // ryukyoku by empty yama
+    ExhaustiveDraw,
+    // abortive draws
+    KyushuKyuhai,
+    FourWinds,
+    FourReach,
+    FourKans,
+    TripleRon,
+    // by the server admin
+    Canceled,
+}
+
+impl RoundEnd {
+    pub fn is_abort(&self) -> bool {
+        matches!(
+            self,
+            Self::KyushuKyuhai
+                | Self::FourWinds
+                | Self::FourReach
+                | Self::FourKans
+                | Self::TripleRon
+                | Self::Canceled
+        )
+    }
+}
+
+// the result of the whole game
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalResult {
+    // sorted by rank
+    pub standings: Vec<Standing>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Standing {
+    pub player: u32,
+    // 0 origin
+    pub rank: u32,
+    // final point (with deposit)
+    pub point: i32,
+    // with uma and oka (1000 = 1.0)
+    pub score: i32,
+}
+
+// game history (seq starts from 1)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub seq: u64,
+    pub event: GameEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EventList(pub Vec<Event>);
+
+// hidden pais are -1 (or empty hands) in the player's event list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum GameEvent {
+    // after haipai
+    RoundStart {
+        wind: u32,
+        parent: u32,
+        hon: u32,
+        deposit: u32,
+        // dora indicator
+        dora: i32,
+        points: Vec<i32>,
+        hands: Vec<Vec<i32>>,
+        // the rest of the wall (drawn from the last) and wang pai, empty for players
+        yama: Vec<i32>,
+        yama2: Vec<i32>,
+    },
+    Draw {
+        player: u32,
+        pai: i32,
+        // from wang pai after kan
+        lingshang: bool,
+    },
+    Discard {
+        player: u32,
+        pai: i32,
+        // the drawn pai
+        tsumogiri: bool,
+        reach: bool,
+    },
+    // reach deposit paid after the discard is accepted
+    ReachAccepted {
+        player: u32,
+    },
+    // Chi/Pon/OpenKan (pai = the called pai), BlindKan/AddedKan (pai = the added pai)
+    Call {
+        player: u32,
+        pai: i32,
+        meld: Meld,
+    },
+    // new dora indicator
+    Dora {
+        indicator: i32,
+    },
+    RoundEnd {
+        result: RoundResult,
+        // ura dora indicators (for each dora)
+        ura: Vec<i32>,
+        points: Vec<i32>,
+    },
+    GameEnd {
+        result: FinalResult,
+    },
+    // from a seat, or a spectator if None
+    Chat {
+        player: Option<u32>,
+        body: ChatBody,
+    },
+}
+
+// canned reactions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Stamp {
+    Hello,
+    GoodLuck,
+    Nice,
+    Thanks,
+    Sorry,
+    Hurry,
+    GoodGame,
+}
+
+impl Stamp {
+    pub const ALL: [Stamp; 7] = [
+        Self::Hello,
+        Self::GoodLuck,
+        Self::Nice,
+        Self::Thanks,
+        Self::Sorry,
+        Self::Hurry,
+        Self::GoodGame,
+    ];
+
+    pub fn text(&self) -> &'static str {
+        match self {
+            Self::Hello => "Hello!",
+            Self::GoodLuck => "Good luck!",
+            Self::Nice => "Nice!",
+            Self::Thanks => "Thanks!",
+            Self::Sorry => "Sorry!",
+            Self::Hurry => "Hurry up!",
+            Self::GoodGame => "Good game!",
+        }
+    }
+}
+
+// POST /api/room/{id}/chat or /api/room/{id}/{player}/chat
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChatBody {
+    Text(String),
+    Stamp(Stamp),
+}
+
+impl ChatBody {
+    pub fn text(&self) -> &str {
+        match self {
+            Self::Text(text) => text,
+            Self::Stamp(stamp) => stamp.text(),
+        }
+    }
+}
+
+// rule options
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleConfig {
+    // 3 players uses no 2m-8m
+    pub player_count: u32,
+    // the last seats are played by server-side CPU players
+    pub cpu_players: u32,
+    // 1: tonpuu, 2: hanchan
+    pub round_max: u32,
+    // one red pai for each 5m, 5p, 5s
+    pub red_five: bool,
+    // kuitan
+    pub open_tanyao: bool,
+    // count double and multiple yakuman
+    pub double_yakuman: bool,
+    // seconds for each decision and extra time bank for each player (0: unlimited)
+    // pass or tsumogiri by the server after time out
+    pub time_limit: u32,
+    pub time_extra: u32,
+    // abort if 3 players declare Ron, or all of them win
+    pub abort_triple_ron: bool,
+    // add a hon after abortive draws (parent continues anyway)
+    pub abort_hon_up: bool,
+    // finish if any player's point is less than 0
+    pub bust_end: bool,
+    // finish if the parent is the top at the last round (agari-yame)
+    pub parent_stop: bool,
+    // go into the next wind if nobody reaches target_point at the last round
+    pub extension: bool,
+    pub target_point: i32,
+    pub start_point: i32,
+    // oka = (return_point - start_point) * player_count
+    pub return_point: i32,
+    // for each rank (1000 = 1.0)
+    pub uma: Vec<i32>,
+    // deposit left at the end goes to the top
+    pub deposit_to_top: bool,
+    // spectators see all hands (spectator_delay must be 16 or more)
+    pub spectator_hands: bool,
+    // spectator view is behind by this number of discards
+    pub spectator_delay: u32,
+}
+
+impl Default for RuleConfig {
+    fn default() -> Self {
+        Self {
+            player_count: 4,
+            cpu_players: 0,
+            round_max: 2,
+            red_five: false,
+            open_tanyao: true,
+            double_yakuman: true,
+            time_limit: 0,
+            time_extra: 0,
+            abort_triple_ron: true,
+            abort_hon_up: true,
+            bust_end: true,
+            parent_stop: false,
+            extension: true,
+            target_point: 30000,
+            start_point: 25000,
+            return_point: 30000,
+            uma: vec![20000, 10000, -10000, -20000],
+            deposit_to_top: true,
+            spectator_hands: false,
+            spectator_delay: 0,
+        }
+    }
+}
+
+// player-dependent data view (index 0 = self)
+// others' hands and draws are empty (-1) unless revealed in the round result
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct LocalState {
     pub points: [i32; 4],
     pub hands: [Vec<i32>; 4],
     pub hands_str: [Vec<String>; 4],
+    // concealed pai count (with the drawn pai)
+    pub hand_counts: [u32; 4],
     pub draws: [i32; 4],
     pub draws_str: [String; 4],
     pub actions: Vec<Action>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum GamePhase {
     WaitAction,
     WaitReaction,
     ShowResult,
+    GameResult,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Action {
     // Active player
+    // hand index (hand.len() means the drawn pai)
     Discard(i32),
+    // discard with reach declaration (the same as Discard)
+    Reach(i32),
     Tsumo,
-    // TODO: param
-    BlindKan,
-    SmallKan,
+    // pai code
+    BlindKan(i32),
+    SmallKan(i32),
+    // abortive draw at the first draw
+    KyushuKyuhai,
 
     // Non-active player
     Skip,
     Ron,
-    // TODO: param
-    Chi,
+    // the lowest pai code of the sequence
+    Chi(i32),
     Pon,
     BigKan,
+
+    // All players at ShowResult
+    Next,
 }
@@ -666,21 +666,9 @@ fn finish_kokushi(hand: &Hand, result: &mut Vec<FinishHand>) -> Result<()> {
         }
         debug_assert!(is_yao(pai));
         match count {
-            0 => {
-                if wait.is_none() {
-                    wait = Some(pai);
-                } else {
-                    return Ok(());
-                }
-            }
+            0 if wait.is_none() => wait = Some(pai),
             1 => {}
-            2 => {
-                if have2.is_none() {
-                    have2 = Some(pai);
-                } else {
-                    return Ok(());
-                }
-            }
+            2 if have2.is_none() => have2 = Some(pai),
             _ => return Ok(()),
         }
     }
@@ -989,7 +977,7 @@ fn calc_fu(hand: &FinishHand, param: &PointParam, menzen: bool) -> u32 {
     }
 
     // roundup 10
-    (fu + 9) / 10 * 10
+    fu.div_ceil(10) * 10
 }
 
 pub fn calc_base_point(hand: &FinishHand, param: &PointParam) -> Point {
@@ -1009,7 +997,7 @@ pub fn calc_base_point(hand: &FinishHand, param: &PointParam) -> Point {
     let fan1 = Yaku::fan_sum(yaku);
     let mut fan2 = 0;
     for pai in hand.to_pai_list() {
-        for &dora in param.dora.iter() {
+        for &dora in param.dora.iter().chain(param.ura.iter()) {
             if pai == dora {
                 fan2 += 1;
             }
@@ -1044,7 +1032,7 @@ pub fn calc_base_point_direct(
 }
 
 fn roundup100(x: u32) -> u32 {
-    (x + 99) / 100 * 100
+    x.div_ceil(100) * 100
 }
 
 // -----------------------------------------------------------------------------
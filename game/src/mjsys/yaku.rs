@@ -375,7 +375,7 @@ pub fn check_yaku(hand: &FinishHand, param: &PointParam, menzen: bool) -> u64 {
                 exist[num as usize] |= 1 << kind;
             }
         }
-        if exist.iter().any(|&bits| bits == 0b111) {
+        if exist.contains(&0b111) {
             yaku |= if menzen {
                 Yaku::DOJUN.0
             } else {
@@ -457,7 +457,7 @@ pub fn check_yaku(hand: &FinishHand, param: &PointParam, menzen: bool) -> u64 {
                 exist[num as usize] |= 1 << kind;
             }
         }
-        if exist.iter().any(|&bits| bits == 0b111) {
+        if exist.contains(&0b111) {
             yaku |= Yaku::DOKO.0;
         }
     }
@@ -17,7 +17,10 @@ fn version() -> Result<()> {
 fn trunk_build_release(out_dir: &Path, debug: bool) -> Result<()> {
     const PROJ_ROOT: &str = "../client";
     const PROJ_DEP: [&str; 1] = ["../game"];
-    const PUBLIC_URL: &str = "/playsv";
+    // PUBLIC_URL=/foo cargo build
+    println!("cargo:rerun-if-env-changed=PUBLIC_URL");
+    let public_url = env::var("PUBLIC_URL").unwrap_or_else(|_| "/playsv".to_string());
+    let public_url = public_url.trim_end_matches('/');
     let dist: &str = if debug { "dist_debug" } else { "dist_release" };
 
     println!("cargo:rerun-if-changed={PROJ_ROOT}/build.rs");
@@ -28,10 +31,10 @@ fn trunk_build_release(out_dir: &Path, debug: bool) -> Result<()> {
     }
 
     // for trunk param and client compile parameter
-    println!("cargo:rustc-env=PUBLIC_URL={PUBLIC_URL}");
+    println!("cargo:rustc-env=PUBLIC_URL={public_url}");
 
     let mut cmd = Command::new("trunk");
-    cmd.env("PUBLIC_URL", PUBLIC_URL);
+    cmd.env("PUBLIC_URL", public_url);
     cmd.arg("build");
     if debug {
         // nothing
@@ -45,7 +48,7 @@ fn trunk_build_release(out_dir: &Path, debug: bool) -> Result<()> {
         .arg("--filehash")
         .arg("false")
         .arg("--public-url")
-        .arg(&format!("{}/", PUBLIC_URL))
+        .arg(format!("{}/", public_url))
         .current_dir(PROJ_ROOT);
     let output = cmd.output().expect("failed to execute trunk");
 
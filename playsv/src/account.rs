@@ -0,0 +1,428 @@
+use crate::stats::StatCounts;
+use anyhow::{bail, ensure, Context, Result};
+use game::jsif;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/*
+accounts.json in the data directory (in memory only without it)
+login sessions are not saved and expire after SESSION_EXPIRE
+
+password: pbkdf2-sha256$<iterations>$<salt hex>$<hash hex>
+(the scheme is checked to upgrade hashes later)
+rating: tenhou style, only when all seats are accounts (no CPU or guest)
+*/
+
+const ACCOUNTS_FILE: &str = "accounts.json";
+const NAME_MAX: usize = 32;
+const PASSWORD_MIN: usize = 8;
+// bounds the hashing cost
+const PASSWORD_MAX: usize = 256;
+// OWASP recommendation (fewer for unoptimized tests)
+const HASH_ITERATIONS: u32 = if cfg!(test) { 1000 } else { 600_000 };
+const HASH_SCHEME: &str = "pbkdf2-sha256";
+const SALT_BYTES: usize = 16;
+const SESSION_BYTES: usize = 16;
+const SESSION_EXPIRE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+pub const RATING_START: f64 = 1500.0;
+// for the 1st place, down to the negative for the last
+const RATING_BONUS: f64 = 30.0;
+// the correction factor goes down to the min by the game count
+const RATING_DECAY: f64 = 0.002;
+const RATING_CORRECTION_MIN: f64 = 0.2;
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    name: String,
+    password: String,
+    rating: f64,
+    games: u32,
+    history: Vec<jsif::RatingChange>,
+    #[serde(default)]
+    stats: StatCounts,
+}
+
+impl Account {
+    fn to_jsif(&self) -> jsif::AccountInfo {
+        jsif::AccountInfo {
+            name: self.name.clone(),
+            rating: self.rating,
+            games: self.games,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct AccountStore {
+    // saved at every change if Some
+    dir: Option<PathBuf>,
+    // name -> account
+    accounts: BTreeMap<String, Account>,
+    // session -> (name, login time)
+    sessions: HashMap<String, (String, Instant)>,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn random_hex(len: usize) -> String {
+    // thread_local cryptographically secure PRNG
+    let mut rng = rand::thread_rng();
+    let bytes: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+    to_hex(&bytes)
+}
+
+// RFC 8018 PBKDF2-HMAC-SHA256
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut result = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut result);
+
+    result
+}
+
+// slow, call without locks
+pub fn hash_password(password: &str) -> String {
+    let salt = random_hex(SALT_BYTES);
+    let hash = pbkdf2_sha256(password.as_bytes(), salt.as_bytes(), HASH_ITERATIONS);
+
+    format!("{HASH_SCHEME}${HASH_ITERATIONS}${salt}${}", to_hex(&hash))
+}
+
+// slow, call without locks
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let [scheme, iterations, salt, hash] = parts[..] else {
+        return false;
+    };
+    let Ok(iterations) = iterations.parse() else {
+        return false;
+    };
+    if scheme != HASH_SCHEME {
+        return false;
+    }
+    let actual = to_hex(&pbkdf2_sha256(
+        password.as_bytes(),
+        salt.as_bytes(),
+        iterations,
+    ));
+
+    // constant time
+    actual.len() == hash.len()
+        && actual
+            .bytes()
+            .zip(hash.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+pub fn validate(cred: &jsif::Credentials) -> Result<()> {
+    let name = &cred.name;
+    ensure!(
+        !name.is_empty() && name.len() <= NAME_MAX,
+        "Name must be 1 to {} bytes",
+        NAME_MAX
+    );
+    ensure!(
+        name.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-'),
+        "Name must be alphanumeric, '_' or '-'"
+    );
+    ensure!(
+        (PASSWORD_MIN..=PASSWORD_MAX).contains(&cred.password.len()),
+        "Password must be {} to {} bytes",
+        PASSWORD_MIN,
+        PASSWORD_MAX
+    );
+
+    Ok(())
+}
+
+// tenhou style: (bonus by rank + (average - rating) / 40) * correction
+pub fn rating_delta(rating: f64, games: u32, average: f64, rank: u32, count: u32) -> f64 {
+    let bonus = if count > 1 {
+        RATING_BONUS - 2.0 * RATING_BONUS * rank as f64 / (count - 1) as f64
+    } else {
+        0.0
+    };
+    let correction = (1.0 - games as f64 * RATING_DECAY).max(RATING_CORRECTION_MIN);
+
+    (bonus + (average - rating) / 40.0) * correction
+}
+
+impl AccountStore {
+    // empty if no file
+    pub fn load(dir: Option<&Path>) -> Result<Self> {
+        let mut store = Self {
+            dir: dir.map(Path::to_path_buf),
+            ..Default::default()
+        };
+        let Some(dir) = dir else {
+            return Ok(store);
+        };
+        let path = dir.join(ACCOUNTS_FILE);
+        if path.exists() {
+            let json = fs::read_to_string(&path)?;
+            let list: Vec<Account> = serde_json::from_str(&json)
+                .with_context(|| format!("Invalid {}", path.display()))?;
+            store.accounts = list.into_iter().map(|a| (a.name.clone(), a)).collect();
+            log::info!("Restored {} accounts", store.accounts.len());
+        }
+
+        Ok(store)
+    }
+
+    // write to a temporary file and rename
+    fn save(&self) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+        let list: Vec<&Account> = self.accounts.values().collect();
+        let json = serde_json::to_string(&list)?;
+        let path = dir.join(ACCOUNTS_FILE);
+        let tmp = path.with_extension("tmp");
+        fs::create_dir_all(dir)?;
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+
+    // password_hash by hash_password()
+    pub fn register(&mut self, name: &str, password_hash: String) -> Result<jsif::AccountInfo> {
+        ensure!(!self.accounts.contains_key(name), "Name already taken");
+        let account = Account {
+            name: name.to_string(),
+            password: password_hash,
+            rating: RATING_START,
+            games: 0,
+            history: vec![],
+            stats: Default::default(),
+        };
+        let info = account.to_jsif();
+        self.accounts.insert(name.to_string(), account);
+        self.save()?;
+
+        Ok(info)
+    }
+
+    // for verify_password()
+    pub fn password_hash(&self, name: &str) -> Option<String> {
+        self.accounts.get(name).map(|a| a.password.clone())
+    }
+
+    // after verify_password()
+    pub fn login(&mut self, name: &str, now: Instant) -> jsif::Session {
+        self.sessions
+            .retain(|_, (_, since)| now.saturating_duration_since(*since) < SESSION_EXPIRE);
+        let session = random_hex(SESSION_BYTES);
+        self.sessions
+            .insert(session.clone(), (name.to_string(), now));
+
+        jsif::Session {
+            name: name.to_string(),
+            session,
+        }
+    }
+
+    // account name
+    pub fn session_name(&self, session: &str, now: Instant) -> Result<String> {
+        match self.sessions.get(session) {
+            Some((name, since)) if now.saturating_duration_since(*since) < SESSION_EXPIRE => {
+                Ok(name.clone())
+            }
+            _ => bail!("Invalid session"),
+        }
+    }
+
+    pub fn logout(&mut self, session: &str) -> Result<()> {
+        ensure!(self.sessions.remove(session).is_some(), "Invalid session");
+
+        Ok(())
+    }
+
+    pub fn info(&self, name: &str) -> Result<jsif::AccountInfo> {
+        match self.accounts.get(name) {
+            Some(account) => Ok(account.to_jsif()),
+            None => bail!("No account: {}", name),
+        }
+    }
+
+    pub fn history(&self, name: &str) -> Result<jsif::RatingHistory> {
+        match self.accounts.get(name) {
+            Some(account) => Ok(jsif::RatingHistory(account.history.clone())),
+            None => bail!("No account: {}", name),
+        }
+    }
+
+    pub fn stats(&self, name: &str) -> Result<jsif::PlayerStats> {
+        match self.accounts.get(name) {
+            Some(account) => Ok(account.stats.to_jsif(name)),
+            None => bail!("No account: {}", name),
+        }
+    }
+
+    pub fn ranking(&self) -> jsif::AccountList {
+        let mut list: Vec<_> = self.accounts.values().map(Account::to_jsif).collect();
+        list.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+
+        jsif::AccountList(list)
+    }
+
+    // (account name, rank) for each seat and the event log after the game
+    // rating and stats are updated once for each room
+    pub fn record_game(
+        &mut self,
+        room: u64,
+        ranks: &[(Option<String>, u32)],
+        events: &[jsif::Event],
+        time: u64,
+    ) -> Result<()> {
+        // CPU or guest seats
+        if ranks.iter().any(|(name, _)| name.is_none()) {
+            return Ok(());
+        }
+        let count = ranks.len() as u32;
+        let ratings: Vec<f64> = ranks
+            .iter()
+            .map(|(name, _)| {
+                name.as_ref()
+                    .and_then(|name| self.accounts.get(name))
+                    .map_or(RATING_START, |a| a.rating)
+            })
+            .collect();
+        let average = ratings.iter().sum::<f64>() / count as f64;
+
+        let mut rated = false;
+        for (player, (name, rank)) in ranks.iter().enumerate() {
+            let Some(account) = name.as_ref().and_then(|name| self.accounts.get_mut(name)) else {
+                continue;
+            };
+            if account.history.iter().any(|h| h.room == room) {
+                continue;
+            }
+            let delta = rating_delta(account.rating, account.games, average, *rank, count);
+            account.rating += delta;
+            account.games += 1;
+            account.history.push(jsif::RatingChange {
+                room,
+                time,
+                rank: *rank,
+                player_count: count,
+                rating: account.rating,
+                delta,
+            });
+            account.stats.add_game(events, player as u32);
+            log::info!(room = room; "Rating {}: {:.1} ({:+.1})", account.name, account.rating, delta);
+            rated = true;
+        }
+        if rated {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password() {
+        // RFC 6070 inputs with SHA-256
+        assert_eq!(
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+            to_hex(&pbkdf2_sha256(b"password", b"salt", 1))
+        );
+        assert_eq!(
+            "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a",
+            to_hex(&pbkdf2_sha256(b"password", b"salt", 4096))
+        );
+
+        let hash = hash_password("password");
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("passwore", &hash));
+        assert!(!verify_password("password", "plain"));
+        // old scheme
+        let old = hash.replace(HASH_SCHEME, "pbkdf2-sha1");
+        assert!(!verify_password("password", &old));
+        // salted
+        assert_ne!(hash, hash_password("password"));
+    }
+
+    #[test]
+    fn rating() {
+        let cred = |name: &str, password: &str| jsif::Credentials {
+            name: name.to_string(),
+            password: password.to_string(),
+        };
+        assert!(validate(&cred("alice", "password")).is_ok());
+        assert!(validate(&cred("", "password")).is_err());
+        assert!(validate(&cred("a b", "password")).is_err());
+        assert!(validate(&cred("alice", "short")).is_err());
+
+        // 4 players at the start
+        assert_eq!(30.0, rating_delta(1500.0, 0, 1500.0, 0, 4));
+        assert_eq!(-10.0, rating_delta(1500.0, 0, 1500.0, 2, 4));
+        assert_eq!(0.0, rating_delta(1500.0, 0, 1500.0, 1, 3));
+        // higher rating gains less, the correction decays
+        assert_eq!(27.5, rating_delta(1600.0, 0, 1500.0, 0, 4));
+        assert_eq!(6.0, rating_delta(1500.0, 1000, 1500.0, 0, 4));
+
+        let mut store = AccountStore::default();
+        store.register("alice", "x".to_string()).unwrap();
+        store.register("bob", "x".to_string()).unwrap();
+        store.register("carol", "x".to_string()).unwrap();
+        store.register("dave", "x".to_string()).unwrap();
+        assert!(store.register("bob", "x".to_string()).is_err());
+
+        // sessions
+        let now = Instant::now();
+        let session = store.login("bob", now).session;
+        assert_eq!("bob", store.session_name(&session, now).unwrap());
+        assert!(store.session_name("xxx", now).is_err());
+        assert!(store.session_name(&session, now + SESSION_EXPIRE).is_err());
+        let later = now + SESSION_EXPIRE;
+        let session2 = store.login("bob", later).session;
+        assert_eq!(1, store.sessions.len());
+        store.logout(&session2).unwrap();
+        assert!(store.session_name(&session2, later).is_err());
+        assert!(store.logout(&session2).is_err());
+
+        // not rated with a guest or CPU
+        let mut ranks = vec![
+            (Some("bob".to_string()), 0),
+            (None, 1),
+            (Some("alice".to_string()), 2),
+            (Some("dave".to_string()), 3),
+        ];
+        store.record_game(2, &ranks, &[], 100).unwrap();
+        assert_eq!(0, store.info("bob").unwrap().games);
+
+        ranks[1].0 = Some("carol".to_string());
+        store.record_game(3, &ranks, &[], 100).unwrap();
+        // once for each room
+        store.record_game(3, &ranks, &[], 100).unwrap();
+        assert_eq!(1530.0, store.info("bob").unwrap().rating);
+        let history = store.history("alice").unwrap().0;
+        assert_eq!(1, history.len());
+        assert_eq!(
+            (3, 2, -10.0),
+            (history[0].room, history[0].rank, history[0].delta)
+        );
+        let ranking = store.ranking().0;
+        assert_eq!(
+            vec!["bob", "carol", "alice", "dave"],
+            ranking.iter().map(|a| &a.name).collect::<Vec<_>>()
+        );
+        assert_eq!(1, ranking[0].games);
+    }
+}
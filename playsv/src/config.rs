@@ -0,0 +1,177 @@
+use crate::mjai;
+use crate::server::{RoomConfig, ServerConfig};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/*
+config file (TOML), all keys are optional
+
+bind = ["0.0.0.0", "::"]
+port = 8888
+cors_origins = ["http://127.0.0.1:8080"]
+workers = 4
+data_dir = "data"
+mjai_bot = "tcp:127.0.0.1:11600"
+log_json = false
+admin_token = "secret"  # admin API is disabled if omitted
+
+[room]
+max_rooms = 100
+idle_expire = 3600      # secs
+finished_expire = 600   # secs
+string_max = 1024       # bytes
+json_limit = 16384      # bytes
+rooms_per_min = 5       # per client IP, 0 for unlimited
+actions_per_sec = 20    # per client IP, 0 for unlimited
+chats_per_min = 20      # per client IP, 0 for unlimited
+logins_per_min = 10     # per client IP, 0 for unlimited
+queue_bot_fill = 30     # secs, CPU players fill the queue if specified
+*/
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub bind: Option<Vec<String>>,
+    pub port: Option<u16>,
+    pub cors_origins: Option<Vec<String>>,
+    pub workers: Option<usize>,
+    pub data_dir: Option<PathBuf>,
+    pub mjai_bot: Option<String>,
+    // applied by logging::init()
+    pub log_json: Option<bool>,
+    pub admin_token: Option<String>,
+    pub room: RoomSection,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomSection {
+    pub max_rooms: Option<usize>,
+    pub idle_expire: Option<u64>,
+    pub finished_expire: Option<u64>,
+    pub string_max: Option<usize>,
+    pub json_limit: Option<usize>,
+    pub rooms_per_min: Option<u32>,
+    pub actions_per_sec: Option<u32>,
+    pub chats_per_min: Option<u32>,
+    pub logins_per_min: Option<u32>,
+    pub queue_bot_fill: Option<u64>,
+}
+
+impl FileConfig {
+    pub fn parse(src: &str) -> Result<Self> {
+        Ok(toml::from_str(src)?)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let src =
+            fs::read_to_string(path).with_context(|| format!("Cannot read {}", path.display()))?;
+
+        Self::parse(&src).with_context(|| format!("Invalid config {}", path.display()))
+    }
+
+    // overwrite only the specified values
+    pub fn apply(&self, server: &mut ServerConfig, room: &mut RoomConfig) -> Result<()> {
+        if let Some(bind) = &self.bind {
+            server.bind = bind.clone();
+        }
+        if let Some(port) = self.port {
+            server.port = port;
+        }
+        if let Some(origins) = &self.cors_origins {
+            server.cors_origins = origins.clone();
+        }
+        if let Some(workers) = self.workers {
+            server.workers = Some(workers);
+        }
+        if let Some(dir) = &self.data_dir {
+            server.data_dir = Some(dir.clone());
+        }
+        if let Some(token) = &self.admin_token {
+            room.admin_token = Some(token.clone());
+        }
+        if let Some(bot) = &self.mjai_bot {
+            room.mjai_bot = Some(mjai::BotTarget::parse(bot)?);
+        }
+
+        let section = &self.room;
+        if let Some(max) = section.max_rooms {
+            room.max_rooms = max;
+        }
+        if let Some(secs) = section.idle_expire {
+            room.idle_expire = Duration::from_secs(secs);
+        }
+        if let Some(secs) = section.finished_expire {
+            room.finished_expire = Duration::from_secs(secs);
+        }
+        if let Some(max) = section.string_max {
+            room.string_max = max;
+        }
+        if let Some(limit) = section.json_limit {
+            room.json_limit = limit;
+        }
+        if let Some(count) = section.rooms_per_min {
+            room.rooms_per_min = count;
+        }
+        if let Some(count) = section.actions_per_sec {
+            room.actions_per_sec = count;
+        }
+        if let Some(count) = section.chats_per_min {
+            room.chats_per_min = count;
+        }
+        if let Some(count) = section.logins_per_min {
+            room.logins_per_min = count;
+        }
+        if let Some(secs) = section.queue_bot_fill {
+            room.queue_bot_fill = Some(Duration::from_secs(secs));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply() {
+        let src = r#"
+bind = ["0.0.0.0", "::"]
+port = 9999
+cors_origins = ["http://192.168.0.2:8080"]
+data_dir = "data"
+
+[room]
+max_rooms = 10
+idle_expire = 60
+actions_per_sec = 0
+"#;
+        let file = FileConfig::parse(src).unwrap();
+        let mut server = ServerConfig::default();
+        let mut room = RoomConfig::default();
+        file.apply(&mut server, &mut room).unwrap();
+
+        assert_eq!(vec!["0.0.0.0", "::"], server.bind);
+        assert_eq!(9999, server.port);
+        assert_eq!(vec!["http://192.168.0.2:8080"], server.cors_origins);
+        assert_eq!(None, server.workers);
+        assert_eq!(Some(PathBuf::from("data")), server.data_dir);
+        assert_eq!(10, room.max_rooms);
+        assert_eq!(Duration::from_secs(60), room.idle_expire);
+        assert_eq!(0, room.actions_per_sec);
+        // unchanged
+        let default = RoomConfig::default();
+        assert_eq!(default.finished_expire, room.finished_expire);
+        assert_eq!(default.string_max, room.string_max);
+
+        // empty is valid
+        assert!(FileConfig::parse("").is_ok());
+        // typo
+        assert!(FileConfig::parse("prot = 1").is_err());
+        assert!(FileConfig::parse("[room]\nmax_room = 1").is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::mjgame;
+use game::{jsif, mjsys, mjsys::shanten};
+
+// built-in CPU player
+// decides with the same LocalView as humans (hands[0] is self)
+pub fn decide(view: &jsif::LocalView, player: u32) -> Option<jsif::Action> {
+    let actions = &view.local.actions;
+    if actions.is_empty() {
+        return None;
+    }
+
+    // win if possible (the actions are given only with yaku), no calls
+    let simple = [
+        jsif::Action::Tsumo,
+        jsif::Action::Ron,
+        jsif::Action::Next,
+        jsif::Action::Skip,
+    ];
+    if let Some(&action) = simple.iter().find(|a| actions.contains(a)) {
+        return Some(action);
+    }
+
+    Some(discard(view, player))
+}
+
+// pass, or discard the drawn pai (for time outs and invalid bot decisions)
+// actions must not be empty
+pub fn default_action(view: &jsif::LocalView) -> jsif::Action {
+    let actions = &view.local.actions;
+    [jsif::Action::Next, jsif::Action::Skip]
+        .into_iter()
+        .find(|a| actions.contains(a))
+        .unwrap_or_else(|| {
+            let tsumogiri = jsif::Action::Discard(view.local.hands[0].len() as i32);
+            if actions.contains(&tsumogiri) {
+                tsumogiri
+            } else {
+                actions[0]
+            }
+        })
+}
+
+// keep value for the tie-break (lower is discarded first)
+fn keep_value(pai: i32) -> u32 {
+    let (kind, num) = mjsys::decode(jsif::pai_code(pai) as u8);
+    if kind == mjsys::KIND_Z {
+        0
+    } else {
+        // 1 for terminals, 4 for 5
+        5 - (num as i32 - 5).unsigned_abs()
+    }
+}
+
+fn discard(view: &jsif::LocalView, player: u32) -> jsif::Action {
+    let common = &view.common;
+    let actions = &view.local.actions;
+    let mut pais = view.local.hands[0].clone();
+    if view.local.draws[0] >= 0 {
+        pais.push(view.local.draws[0]);
+    }
+    let melds = &common.melds[player as usize];
+
+    // (action, discarded pai, shanten after the discard)
+    let candidates: Vec<(jsif::Action, i32, u8)> = actions
+        .iter()
+        .filter_map(|&a| match a {
+            jsif::Action::Discard(i) | jsif::Action::Reach(i) => {
+                let mut rest = pais.clone();
+                let pai = rest.remove(i as usize);
+                let shanten = shanten::all(&mjgame::to_mjhand(&rest, melds));
+                Some((a, pai, shanten))
+            }
+            _ => None,
+        })
+        .collect();
+    let best = |list: &[(jsif::Action, i32, u8)]| {
+        list.iter()
+            .min_by_key(|(_, pai, shanten)| (*shanten, keep_value(*pai)))
+            .map(|&(a, _, _)| a)
+    };
+
+    // reach when tenpai
+    let reach: Vec<_> = candidates
+        .iter()
+        .filter(|(a, _, _)| matches!(a, jsif::Action::Reach(_)))
+        .copied()
+        .collect();
+    if let Some(action) = best(&reach) {
+        return action;
+    }
+
+    let discards: Vec<_> = candidates
+        .iter()
+        .filter(|(a, _, _)| matches!(a, jsif::Action::Discard(_)))
+        .copied()
+        .collect();
+    let tenpai = discards.iter().any(|&(_, _, shanten)| shanten == 0);
+
+    // fold from reach players with genbutsu if not tenpai
+    let reach_players: Vec<usize> = (0..common.player_count as usize)
+        .filter(|&p| p != player as usize && common.reach[p])
+        .collect();
+    if !reach_players.is_empty() && !tenpai {
+        let safe: Vec<_> = discards
+            .iter()
+            .filter(|(_, pai, _)| {
+                reach_players.iter().all(|&p| {
+                    common.rivers[p]
+                        .iter()
+                        .any(|&x| jsif::pai_code(x) == jsif::pai_code(*pai))
+                })
+            })
+            .copied()
+            .collect();
+        if let Some(action) = best(&safe) {
+            return action;
+        }
+    }
+
+    best(&discards).unwrap_or(actions[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mjgame::Game;
+
+    #[test]
+    fn cpu_only_game() {
+        let rule = jsif::RuleConfig {
+            cpu_players: 4,
+            round_max: 1,
+            ..Default::default()
+        };
+        let game = Game::new(rule).unwrap();
+        let mut wins = 0;
+        // each round needs < 200 actions
+        for _ in 0..100000 {
+            if game.phase() == jsif::GamePhase::GameResult {
+                break;
+            }
+            for p in 0..4 {
+                let view = game.get_view(p).unwrap();
+                if let Some(action) = decide(&view, p) {
+                    if matches!(action, jsif::Action::Tsumo | jsif::Action::Ron) {
+                        wins += 1;
+                    }
+                    game.action(p, action).unwrap();
+                }
+            }
+        }
+        assert_eq!(jsif::GamePhase::GameResult, game.phase());
+        // not only exhaustive draws
+        assert!(wins > 0);
+    }
+}
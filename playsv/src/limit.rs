@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// remove full buckets if more clients than this (before expire)
+const CLIENT_MAX: usize = 10000;
+
+// token bucket for each client IP address
+// capacity = count, refilled by count / per
+pub struct RateLimiter {
+    // unlimited if 0
+    count: u32,
+    per: Duration,
+    buckets: Mutex<HashMap<IpAddr, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn new(count: u32, per: Duration) -> Self {
+        Self {
+            count,
+            per,
+            buckets: Default::default(),
+        }
+    }
+
+    // take a token if available
+    pub fn check(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.count == 0 {
+            return true;
+        }
+        let capacity = self.count as f64;
+        let refill = |tokens: f64, last: Instant| {
+            let elapsed = now.saturating_duration_since(last).as_secs_f64();
+            (tokens + elapsed * capacity / self.per.as_secs_f64()).min(capacity)
+        };
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= CLIENT_MAX && !buckets.contains_key(&ip) {
+            buckets.retain(|_, &mut (tokens, last)| refill(tokens, last) < capacity);
+        }
+        let (tokens, last) = buckets.entry(ip).or_insert((capacity, now));
+        *tokens = refill(*tokens, *last);
+        *last = now;
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // forget clients not seen for `per` (full buckets, the same as new ones)
+    pub fn expire(&self, now: Instant) {
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.retain(|_, &mut (_, last)| now.saturating_duration_since(last) < self.per);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let a: IpAddr = [192, 168, 0, 1].into();
+        let b: IpAddr = [192, 168, 0, 2].into();
+        let start = Instant::now();
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+
+        // burst up to the count
+        for _ in 0..3 {
+            assert!(limiter.check(a, start));
+        }
+        assert!(!limiter.check(a, start));
+        // for each address
+        assert!(limiter.check(b, start));
+        // one token per 20 secs
+        assert!(!limiter.check(a, start + Duration::from_secs(19)));
+        assert!(limiter.check(a, start + Duration::from_secs(20)));
+        assert!(!limiter.check(a, start + Duration::from_secs(20)));
+
+        // by last seen
+        limiter.expire(start + Duration::from_secs(60));
+        assert_eq!(1, limiter.len());
+        limiter.expire(start + Duration::from_secs(80));
+        assert_eq!(0, limiter.len());
+
+        let unlimited = RateLimiter::new(0, Duration::from_secs(60));
+        for _ in 0..100 {
+            assert!(unlimited.check(a, start));
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use anyhow::{bail, ensure, Result};
+use game::jsif;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// remove tickets not polled for this duration (left the lobby)
+const TICKET_EXPIRE: Duration = Duration::from_secs(60);
+
+// built-in rule presets for the queue
+pub fn default_presets() -> Vec<(String, jsif::RuleConfig)> {
+    let tonpuu = jsif::RuleConfig {
+        round_max: 1,
+        ..Default::default()
+    };
+    let hanchan = jsif::RuleConfig::default();
+    let sanma = jsif::RuleConfig {
+        player_count: 3,
+        start_point: 35000,
+        return_point: 40000,
+        target_point: 40000,
+        uma: vec![15000, 0, -15000],
+        ..Default::default()
+    };
+
+    vec![
+        ("tonpuu".to_string(), tonpuu),
+        ("hanchan".to_string(), hanchan),
+        ("sanma".to_string(), sanma),
+    ]
+}
+
+struct Entry {
+    preset: usize,
+    // arrival order
+    seq: u64,
+    since: Instant,
+    last_poll: Instant,
+    // seated as the account if Some
+    account: Option<String>,
+    // (room id, seat)
+    matched: Option<(u64, jsif::SeatToken)>,
+}
+
+// players to be seated in a new room
+#[derive(Debug)]
+pub struct Table {
+    pub preset: String,
+    // cpu_players for the empty seats
+    pub rule: jsif::RuleConfig,
+    pub tickets: Vec<String>,
+    // for each ticket
+    pub accounts: Vec<Option<String>>,
+}
+
+// waiting players for each preset
+pub struct Lobby {
+    presets: Vec<(String, jsif::RuleConfig)>,
+    next_seq: u64,
+    // ticket -> entry
+    entries: HashMap<String, Entry>,
+}
+
+impl Lobby {
+    pub fn new(presets: Vec<(String, jsif::RuleConfig)>) -> Self {
+        Self {
+            presets,
+            next_seq: 0,
+            entries: Default::default(),
+        }
+    }
+
+    pub fn to_jsif(&self) -> jsif::QueueList {
+        let list = self
+            .presets
+            .iter()
+            .enumerate()
+            .map(|(i, (name, rule))| jsif::QueuePreset {
+                name: name.clone(),
+                rule: rule.clone(),
+                waiting: self.waiting(i),
+            })
+            .collect();
+
+        jsif::QueueList(list)
+    }
+
+    fn waiting(&self, preset: usize) -> u32 {
+        self.entries
+            .values()
+            .filter(|e| e.preset == preset && e.matched.is_none())
+            .count() as u32
+    }
+
+    pub fn enter(
+        &mut self,
+        preset: &str,
+        ticket: String,
+        account: Option<String>,
+        now: Instant,
+    ) -> Result<()> {
+        let index = self.presets.iter().position(|(name, _)| name == preset);
+        let Some(index) = index else {
+            bail!("Invalid preset: {}", preset);
+        };
+        // seated twice otherwise
+        if account.is_some() {
+            ensure!(
+                !self.entries.values().any(|e| e.account == account),
+                "Already in the queue"
+            );
+        }
+        self.entries.insert(
+            ticket,
+            Entry {
+                preset: index,
+                seq: self.next_seq,
+                since: now,
+                last_poll: now,
+                account,
+                matched: None,
+            },
+        );
+        self.next_seq += 1;
+
+        Ok(())
+    }
+
+    // leave the queue (the matched seat is not freed)
+    pub fn cancel(&mut self, ticket: &str) -> Result<()> {
+        ensure!(self.entries.remove(ticket).is_some(), "Invalid ticket");
+
+        Ok(())
+    }
+
+    pub fn status(&mut self, ticket: &str, now: Instant) -> Result<jsif::QueueStatus> {
+        let Some(entry) = self.entries.get_mut(ticket) else {
+            bail!("Invalid ticket");
+        };
+        entry.last_poll = now;
+        if let Some((room, seat)) = &entry.matched {
+            return Ok(jsif::QueueStatus::Matched {
+                room: *room,
+                seat: seat.clone(),
+            });
+        }
+        let preset = entry.preset;
+
+        Ok(jsif::QueueStatus::Waiting {
+            waiting: self.waiting(preset),
+            player_count: self.presets[preset].1.player_count,
+        })
+    }
+
+    // the entries are still waiting until set_matched()
+    // the oldest ones are filled with CPU players after bot_fill if Some
+    pub fn form_tables(&self, now: Instant, bot_fill: Option<Duration>) -> Vec<Table> {
+        let mut tables = vec![];
+        for (i, (name, rule)) in self.presets.iter().enumerate() {
+            let mut waiting: Vec<(&String, &Entry)> = self
+                .entries
+                .iter()
+                .filter(|(_, e)| e.preset == i && e.matched.is_none())
+                .collect();
+            waiting.sort_by_key(|(_, e)| e.seq);
+
+            let count = rule.player_count as usize;
+            for chunk in waiting.chunks(count) {
+                let full = chunk.len() == count;
+                let timeout = bot_fill
+                    .is_some_and(|fill| now.saturating_duration_since(chunk[0].1.since) >= fill);
+                if !full && !timeout {
+                    break;
+                }
+                tables.push(Table {
+                    preset: name.clone(),
+                    rule: jsif::RuleConfig {
+                        cpu_players: (count - chunk.len()) as u32,
+                        ..rule.clone()
+                    },
+                    tickets: chunk.iter().map(|(t, _)| (*t).clone()).collect(),
+                    accounts: chunk.iter().map(|(_, e)| e.account.clone()).collect(),
+                });
+            }
+        }
+
+        tables
+    }
+
+    pub fn set_matched(&mut self, ticket: &str, room: u64, seat: jsif::SeatToken) {
+        if let Some(entry) = self.entries.get_mut(ticket) {
+            entry.matched = Some((room, seat));
+        }
+    }
+
+    // remove tickets nobody polls
+    pub fn expire(&mut self, now: Instant) {
+        self.entries
+            .retain(|_, e| now.saturating_duration_since(e.last_poll) < TICKET_EXPIRE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_presets() {
+        for (name, rule) in default_presets() {
+            assert!(crate::mjgame::Game::new(rule).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn form_tables() {
+        let start = Instant::now();
+        let mut lobby = Lobby::new(default_presets());
+        for i in 0..5 {
+            lobby
+                .enter("hanchan", format!("h{i}"), None, start)
+                .unwrap();
+        }
+        let alice = Some("alice".to_string());
+        lobby
+            .enter("sanma", "s0".to_string(), alice.clone(), start)
+            .unwrap();
+        assert!(lobby
+            .enter("unknown", "x".to_string(), None, start)
+            .is_err());
+        assert!(lobby
+            .enter("hanchan", "x".to_string(), alice.clone(), start)
+            .is_err());
+
+        // only full tables without bot_fill
+        let tables = lobby.form_tables(start, None);
+        assert_eq!(1, tables.len());
+        assert_eq!(vec!["h0", "h1", "h2", "h3"], tables[0].tickets);
+        assert_eq!(0, tables[0].rule.cpu_players);
+        for (player, ticket) in tables[0].tickets.iter().enumerate() {
+            let seat = jsif::SeatToken {
+                player: player as u32,
+                token: "t".to_string(),
+            };
+            lobby.set_matched(ticket, 7, seat);
+        }
+        assert!(matches!(
+            lobby.status("h2", start).unwrap(),
+            jsif::QueueStatus::Matched { room: 7, .. }
+        ));
+        assert!(matches!(
+            lobby.status("h4", start).unwrap(),
+            jsif::QueueStatus::Waiting {
+                waiting: 1,
+                player_count: 4
+            }
+        ));
+        assert_eq!(1, lobby.to_jsif().0[1].waiting);
+
+        // filled with CPU players after the timeout
+        let fill = Some(Duration::from_secs(30));
+        assert!(lobby
+            .form_tables(start + Duration::from_secs(29), fill)
+            .is_empty());
+        let tables = lobby.form_tables(start + Duration::from_secs(30), fill);
+        assert_eq!(2, tables.len());
+        assert_eq!(vec!["h4"], tables[0].tickets);
+        assert_eq!(3, tables[0].rule.cpu_players);
+        assert_eq!("sanma", tables[1].preset);
+        assert_eq!(2, tables[1].rule.cpu_players);
+        assert_eq!(vec![alice], tables[1].accounts);
+
+        // cancel and expiry
+        lobby.cancel("s0").unwrap();
+        assert!(lobby.cancel("s0").is_err());
+        lobby.expire(start + Duration::from_secs(59));
+        assert!(lobby.status("h4", start + Duration::from_secs(59)).is_ok());
+        lobby.expire(start + TICKET_EXPIRE);
+        assert!(lobby.status("h0", start).is_err());
+        assert!(lobby.status("h4", start).is_ok());
+    }
+}
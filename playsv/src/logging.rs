@@ -0,0 +1,92 @@
+use log::kv::{Key, Value, VisitSource};
+use serde_json::{json, Map};
+use std::io::Write;
+
+/*
+RUST_LOG=debug,actix_web=info playsv
+
+text: [2026-01-01T00:00:00.000Z INFO  playsv::server] Action Discard(3) room=0 seat=1
+json: {"time":"...","level":"INFO","target":"playsv::server","msg":"Action Discard(3)","room":0,"seat":1}
+*/
+
+const LEVEL_DEFAULT: &str = "info";
+
+// key-value pairs to a json object
+struct JsonFields(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let value = if let Some(x) = value.to_u64() {
+            json!(x)
+        } else if let Some(x) = value.to_i64() {
+            json!(x)
+        } else if let Some(x) = value.to_bool() {
+            json!(x)
+        } else {
+            json!(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+
+        Ok(())
+    }
+}
+
+fn json_line(time: &str, record: &log::Record) -> serde_json::Value {
+    let mut map = Map::new();
+    map.insert("time".into(), json!(time));
+    map.insert("level".into(), json!(record.level().as_str()));
+    map.insert("target".into(), json!(record.target()));
+    map.insert("msg".into(), json!(record.args().to_string()));
+    let mut fields = JsonFields(map);
+    // ignore a broken pair
+    let _ = record.key_values().visit(&mut fields);
+
+    serde_json::Value::Object(fields.0)
+}
+
+// log level by RUST_LOG (default: info), one json object per line if json
+pub fn init(json: bool) {
+    let env = env_logger::Env::default().default_filter_or(LEVEL_DEFAULT);
+    let mut builder = env_logger::Builder::from_env(env);
+    if json {
+        builder.format(|buf, record| {
+            let time = buf.timestamp_millis().to_string();
+            writeln!(buf, "{}", json_line(&time, record))
+        });
+    }
+    builder.init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_fields() {
+        let kvs: &[(&str, Value)] = &[
+            ("room", Value::from(3u64)),
+            ("seat", Value::from(-1i64)),
+            ("reason", Value::from("Invalid token")),
+        ];
+        let record = log::Record::builder()
+            .level(log::Level::Warn)
+            .target("playsv::server")
+            .args(format_args!("Rejected"))
+            .key_values(&kvs)
+            .build();
+
+        let line = json_line("now", &record);
+        assert_eq!(
+            json!({
+                "time": "now",
+                "level": "WARN",
+                "target": "playsv::server",
+                "msg": "Rejected",
+                "room": 3,
+                "seat": -1,
+                "reason": "Invalid token",
+            }),
+            line
+        );
+    }
+}
@@ -1,11 +1,26 @@
+mod account;
+mod config;
+mod cpu;
+mod limit;
+mod lobby;
+mod logging;
+mod metrics;
+mod mjai;
 mod mjgame;
 mod server;
+mod sim;
+mod stats;
+mod tenhou;
+mod timer;
 
 use anyhow::Result;
 use getopts::{Matches, Options};
 use std::env;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-const PORT_DEFAULT: u16 = 8888;
+// origin of `trunk serve`
+const CORS_ORIGIN_DEFAULT: &str = "http://127.0.0.1:8080";
 
 fn parse_options() -> Result<Matches> {
     let args: Vec<String> = env::args().collect();
@@ -13,8 +28,49 @@ fn parse_options() -> Result<Matches> {
 
     let mut opts = Options::new();
     opts.optflag("h", "help", "Print this help");
+    opts.optopt("", "config", "Config file (TOML)", "FILE");
+    opts.optmulti("b", "bind", "Bind address (repeatable)", "ADDR");
     opts.optopt("p", "port", "Port number", "PORT");
-    opts.optflag("c", "cors", "Enable CORS");
+    opts.optflag("c", "cors", "Enable CORS for trunk serve");
+    opts.optmulti(
+        "",
+        "cors-origin",
+        "Allowed CORS origin (repeatable)",
+        "ORIGIN",
+    );
+    opts.optopt("w", "workers", "Worker thread count", "COUNT");
+    opts.optopt("m", "max-rooms", "Max room count", "COUNT");
+    opts.optopt("", "string-max", "Max comment length", "BYTES");
+    opts.optopt("", "json-limit", "Max JSON request size", "BYTES");
+    opts.optopt("", "rooms-per-min", "Room creation limit per IP", "COUNT");
+    opts.optopt("", "actions-per-sec", "Action limit per IP", "COUNT");
+    opts.optopt("", "chats-per-min", "Chat message limit per IP", "COUNT");
+    opts.optopt("", "logins-per-min", "Login limit per IP", "COUNT");
+    opts.optopt("", "idle-expire", "Remove idle rooms after", "SECS");
+    opts.optopt("", "finished-expire", "Remove finished rooms after", "SECS");
+    opts.optopt(
+        "",
+        "queue-bot-fill",
+        "Fill the queue with CPU players after",
+        "SECS",
+    );
+    opts.optopt("d", "data-dir", "Save and restore rooms", "DIR");
+    opts.optopt(
+        "",
+        "mjai-bot",
+        "mjai bot command line or tcp:HOST:PORT",
+        "BOT",
+    );
+    opts.optopt(
+        "",
+        "admin-token",
+        "Enable admin API with the token",
+        "TOKEN",
+    );
+    opts.optflag("", "log-json", "Log in JSON lines (level by RUST_LOG)");
+    opts.optopt("s", "simulate", "Run CPU-only games and exit", "GAMES");
+    opts.optopt("", "seed", "Random seed for --simulate", "SEED");
+    opts.optopt("", "rule", "Rule for --simulate", "JSON");
 
     let m = opts.parse(&args[1..])?;
     if m.opt_present("h") {
@@ -29,10 +85,72 @@ fn parse_options() -> Result<Matches> {
 #[actix_web::main]
 async fn main() -> Result<()> {
     let m = parse_options()?;
-    let port: u16 = m.opt_get_default("p", PORT_DEFAULT)?;
-    let cors = m.opt_present("c");
+    let file = match m.opt_str("config") {
+        Some(path) => config::FileConfig::load(Path::new(&path))?,
+        None => Default::default(),
+    };
+    logging::init(m.opt_present("log-json") || file.log_json == Some(true));
 
-    server::server_main(port, cors).await
+    if let Some(games) = m.opt_get::<u64>("s")? {
+        let rule: game::jsif::RuleConfig = match m.opt_str("rule") {
+            Some(json) => serde_json::from_str(&json)?,
+            None => Default::default(),
+        };
+        let seed = m.opt_get_default("seed", rand::random())?;
+        println!("Seed: {seed}");
+        print!("{}", sim::simulate(&rule, games, seed)?);
+        return Ok(());
+    }
+
+    // default < config file < command line
+    let mut config = server::ServerConfig::default();
+    let mut room_config = server::RoomConfig::default();
+    file.apply(&mut config, &mut room_config)?;
+
+    let bind = m.opt_strs("b");
+    if !bind.is_empty() {
+        config.bind = bind;
+    }
+    config.port = m.opt_get_default("p", config.port)?;
+    let origins = m.opt_strs("cors-origin");
+    if !origins.is_empty() {
+        config.cors_origins = origins;
+    }
+    if m.opt_present("c") && config.cors_origins.is_empty() {
+        config.cors_origins.push(CORS_ORIGIN_DEFAULT.to_string());
+    }
+    if let Some(workers) = m.opt_get::<usize>("w")? {
+        config.workers = Some(workers);
+    }
+    if let Some(dir) = m.opt_str("d") {
+        config.data_dir = Some(PathBuf::from(dir));
+    }
+
+    room_config.max_rooms = m.opt_get_default("m", room_config.max_rooms)?;
+    room_config.string_max = m.opt_get_default("string-max", room_config.string_max)?;
+    room_config.json_limit = m.opt_get_default("json-limit", room_config.json_limit)?;
+    room_config.rooms_per_min = m.opt_get_default("rooms-per-min", room_config.rooms_per_min)?;
+    room_config.actions_per_sec =
+        m.opt_get_default("actions-per-sec", room_config.actions_per_sec)?;
+    room_config.chats_per_min = m.opt_get_default("chats-per-min", room_config.chats_per_min)?;
+    room_config.logins_per_min = m.opt_get_default("logins-per-min", room_config.logins_per_min)?;
+    if let Some(secs) = m.opt_get::<u64>("idle-expire")? {
+        room_config.idle_expire = Duration::from_secs(secs);
+    }
+    if let Some(secs) = m.opt_get::<u64>("finished-expire")? {
+        room_config.finished_expire = Duration::from_secs(secs);
+    }
+    if let Some(secs) = m.opt_get::<u64>("queue-bot-fill")? {
+        room_config.queue_bot_fill = Some(Duration::from_secs(secs));
+    }
+    if let Some(token) = m.opt_str("admin-token") {
+        room_config.admin_token = Some(token);
+    }
+    if let Some(bot) = m.opt_str("mjai-bot") {
+        room_config.mjai_bot = Some(mjai::BotTarget::parse(&bot)?);
+    }
+
+    server::server_main(config, room_config).await
 }
 
 /*
@@ -0,0 +1,183 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/*
+Prometheus text exposition format
+counters only increase, use rate() for per second values
+
+rate(playsv_actions_total[1m])
+histogram_quantile(0.9, rate(playsv_http_request_duration_seconds_bucket[5m]))
+*/
+
+// updated from everywhere
+pub static METRICS: Metrics = Metrics::new();
+
+// upper bounds in secs (+Inf is implicit)
+const LATENCY_BUCKETS: [f64; 8] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+pub struct Metrics {
+    actions: AtomicU64,
+    rejected_actions: AtomicU64,
+    // by status class 1xx..5xx
+    responses: [AtomicU64; 5],
+    // cumulative count for each bucket (and +Inf)
+    latency_buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_us: AtomicU64,
+}
+
+// current values not counted by Metrics
+pub struct Gauges {
+    pub rooms: usize,
+    // (phase name, room count)
+    pub phases: Vec<(String, usize)>,
+    pub wasm_bytes: usize,
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            actions: AtomicU64::new(0),
+            rejected_actions: AtomicU64::new(0),
+            responses: [const { AtomicU64::new(0) }; 5],
+            latency_buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS.len() + 1],
+            latency_sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn action(&self, accepted: bool) {
+        if accepted {
+            self.actions.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.rejected_actions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn response(&self, status: u16, elapsed: Duration) {
+        let class = (status as usize / 100).clamp(1, 5);
+        self.responses[class - 1].fetch_add(1, Ordering::Relaxed);
+
+        let secs = elapsed.as_secs_f64();
+        for (i, bucket) in self.latency_buckets.iter().enumerate() {
+            if LATENCY_BUCKETS.get(i).is_none_or(|&le| secs <= le) {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.latency_sum_us
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn render(&self, gauges: &Gauges) -> String {
+        let load = |x: &AtomicU64| x.load(Ordering::Relaxed);
+        let mut out = String::new();
+        let header = |out: &mut String, name: &str, mtype: &str, help: &str| {
+            writeln!(out, "# HELP {name} {help}").unwrap();
+            writeln!(out, "# TYPE {name} {mtype}").unwrap();
+        };
+
+        header(&mut out, "playsv_rooms", "gauge", "Active rooms.");
+        writeln!(out, "playsv_rooms {}", gauges.rooms).unwrap();
+
+        header(&mut out, "playsv_games", "gauge", "Games in each phase.");
+        for (phase, count) in &gauges.phases {
+            writeln!(out, "playsv_games{{phase=\"{phase}\"}} {count}").unwrap();
+        }
+
+        header(
+            &mut out,
+            "playsv_actions_total",
+            "counter",
+            "Accepted actions.",
+        );
+        writeln!(out, "playsv_actions_total {}", load(&self.actions)).unwrap();
+        header(
+            &mut out,
+            "playsv_rejected_actions_total",
+            "counter",
+            "Rejected actions.",
+        );
+        let rejected = load(&self.rejected_actions);
+        writeln!(out, "playsv_rejected_actions_total {rejected}").unwrap();
+
+        header(
+            &mut out,
+            "playsv_http_responses_total",
+            "counter",
+            "HTTP responses by status class.",
+        );
+        for (i, count) in self.responses.iter().enumerate() {
+            let count = load(count);
+            writeln!(
+                out,
+                "playsv_http_responses_total{{class=\"{}xx\"}} {count}",
+                i + 1
+            )
+            .unwrap();
+        }
+
+        let name = "playsv_http_request_duration_seconds";
+        header(&mut out, name, "histogram", "HTTP request latencies.");
+        for (i, bucket) in self.latency_buckets.iter().enumerate() {
+            let le = match LATENCY_BUCKETS.get(i) {
+                Some(le) => le.to_string(),
+                None => "+Inf".to_string(),
+            };
+            writeln!(out, "{name}_bucket{{le=\"{le}\"}} {}", load(bucket)).unwrap();
+        }
+        let sum = load(&self.latency_sum_us) as f64 / 1e6;
+        writeln!(out, "{name}_sum {sum}").unwrap();
+        let count = load(&self.latency_buckets[LATENCY_BUCKETS.len()]);
+        writeln!(out, "{name}_count {count}").unwrap();
+
+        header(
+            &mut out,
+            "playsv_wasm_bytes",
+            "gauge",
+            "Embedded WASM size.",
+        );
+        writeln!(out, "playsv_wasm_bytes {}", gauges.wasm_bytes).unwrap();
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::new();
+        metrics.action(true);
+        metrics.action(true);
+        metrics.action(false);
+        metrics.response(200, Duration::from_millis(3));
+        metrics.response(403, Duration::from_millis(200));
+        let gauges = Gauges {
+            rooms: 2,
+            phases: vec![("WaitAction".to_string(), 1), ("GameResult".to_string(), 1)],
+            wasm_bytes: 1024,
+        };
+
+        let text = metrics.render(&gauges);
+        let lines: Vec<&str> = text.lines().collect();
+        for line in [
+            "playsv_rooms 2",
+            "playsv_games{phase=\"WaitAction\"} 1",
+            "playsv_actions_total 2",
+            "playsv_rejected_actions_total 1",
+            "playsv_http_responses_total{class=\"2xx\"} 1",
+            "playsv_http_responses_total{class=\"4xx\"} 1",
+            "playsv_http_responses_total{class=\"5xx\"} 0",
+            "playsv_http_request_duration_seconds_bucket{le=\"0.001\"} 0",
+            "playsv_http_request_duration_seconds_bucket{le=\"0.005\"} 1",
+            "playsv_http_request_duration_seconds_bucket{le=\"0.5\"} 2",
+            "playsv_http_request_duration_seconds_bucket{le=\"+Inf\"} 2",
+            "playsv_http_request_duration_seconds_sum 0.203",
+            "playsv_http_request_duration_seconds_count 2",
+            "playsv_wasm_bytes 1024",
+        ] {
+            assert!(lines.contains(&line), "{line}");
+        }
+    }
+}
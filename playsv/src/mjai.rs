@@ -0,0 +1,698 @@
+use crate::{cpu, tenhou};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use game::{jsif, mjsys};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/*
+mjai protocol bridge
+
+server -> bot: one JSON message per line
+bot -> server: one JSON line for each message ({"type":"none"} if nothing to do)
+the response to the last message is used as the bot's decision
+reach: the bot sends "reach", gets it back and then sends "dahai"
+*/
+
+const WINDS: [&str; 4] = ["E", "S", "W", "N"];
+const HONORS: [&str; 7] = ["E", "S", "W", "N", "P", "F", "C"];
+
+// -1 (hidden) is "?"
+pub fn to_mjai_pai(pai: i32) -> String {
+    if pai < 0 {
+        return "?".to_string();
+    }
+    let (kind, num) = mjsys::decode(jsif::pai_code(pai) as u8);
+    if kind as usize == 3 {
+        return HONORS[num as usize - 1].to_string();
+    }
+    let red = if jsif::is_red(pai) { "r" } else { "" };
+
+    format!("{num}{}{red}", ['m', 'p', 's'][kind as usize])
+}
+
+pub fn from_mjai_pai(s: &str) -> Result<i32> {
+    if let Some(i) = HONORS.iter().position(|&x| x == s) {
+        return Ok(27 + i as i32);
+    }
+    let (s, red) = match s.strip_suffix('r') {
+        Some(s) => (s, true),
+        None => (s, false),
+    };
+    let mut chars = s.chars();
+    let (num, kind) = (chars.next(), chars.next());
+    ensure!(chars.next().is_none(), "Invalid pai: {}", s);
+    let num = num
+        .and_then(|c| c.to_digit(10))
+        .filter(|n| (1..=9).contains(n))
+        .ok_or_else(|| anyhow!("Invalid pai: {}", s))?;
+    let kind = match kind {
+        Some('m') => 0,
+        Some('p') => 1,
+        Some('s') => 2,
+        _ => bail!("Invalid pai: {}", s),
+    };
+    let code = kind * 9 + num as i32 - 1;
+    ensure!(!red || num == 5, "Invalid pai: {}", s);
+
+    Ok(if red { code | jsif::PAI_RED } else { code })
+}
+
+fn to_mjai_list(pais: &[i32]) -> Vec<String> {
+    pais.iter().map(|&x| to_mjai_pai(x)).collect()
+}
+
+// game events (for the player) to mjai messages
+pub fn to_mjai_messages(event: &jsif::GameEvent) -> Vec<Value> {
+    match event {
+        jsif::GameEvent::RoundStart {
+            wind,
+            parent,
+            hon,
+            deposit,
+            dora,
+            points,
+            hands,
+            ..
+        } => {
+            let tehais: Vec<Vec<String>> = hands
+                .iter()
+                .map(|h| {
+                    if h.is_empty() {
+                        vec!["?".to_string(); 13]
+                    } else {
+                        to_mjai_list(h)
+                    }
+                })
+                .collect();
+            vec![json!({
+                "type": "start_kyoku",
+                "bakaze": WINDS[*wind as usize % 4],
+                "kyoku": parent + 1,
+                "honba": hon,
+                "kyotaku": deposit,
+                "oya": parent,
+                "dora_marker": to_mjai_pai(*dora),
+                "scores": points,
+                "tehais": tehais,
+            })]
+        }
+        jsif::GameEvent::Draw { player, pai, .. } => {
+            vec![json!({"type": "tsumo", "actor": player, "pai": to_mjai_pai(*pai)})]
+        }
+        jsif::GameEvent::Discard {
+            player,
+            pai,
+            tsumogiri,
+            reach,
+        } => {
+            let mut result = vec![];
+            if *reach {
+                result.push(json!({"type": "reach", "actor": player}));
+            }
+            result.push(json!({
+                "type": "dahai",
+                "actor": player,
+                "pai": to_mjai_pai(*pai),
+                "tsumogiri": tsumogiri,
+            }));
+            result
+        }
+        jsif::GameEvent::ReachAccepted { player } => {
+            vec![json!({"type": "reach_accepted", "actor": player})]
+        }
+        jsif::GameEvent::Call { player, pai, meld } => {
+            let (mtype, count) = match meld.mtype {
+                jsif::MeldType::Chi => ("chi", 2),
+                jsif::MeldType::Pon => ("pon", 2),
+                jsif::MeldType::OpenKan => ("daiminkan", 3),
+                jsif::MeldType::AddedKan => ("kakan", 3),
+                jsif::MeldType::BlindKan => ("ankan", 4),
+            };
+            let consumed = to_mjai_list(&tenhou::meld_pais(meld, *pai, count));
+            match meld.mtype {
+                jsif::MeldType::BlindKan => {
+                    vec![json!({"type": mtype, "actor": player, "consumed": consumed})]
+                }
+                jsif::MeldType::AddedKan => vec![json!({
+                    "type": mtype,
+                    "actor": player,
+                    "pai": to_mjai_pai(*pai),
+                    "consumed": consumed,
+                })],
+                _ => vec![json!({
+                    "type": mtype,
+                    "actor": player,
+                    "target": meld.from,
+                    "pai": to_mjai_pai(*pai),
+                    "consumed": consumed,
+                })],
+            }
+        }
+        jsif::GameEvent::Dora { indicator } => {
+            vec![json!({"type": "dora", "dora_marker": to_mjai_pai(*indicator)})]
+        }
+        jsif::GameEvent::RoundEnd {
+            result,
+            ura,
+            points,
+        } => {
+            let mut msgs = vec![];
+            if result.wins.is_empty() {
+                msgs.push(json!({
+                    "type": "ryukyoku",
+                    "deltas": result.deltas,
+                    "scores": points,
+                }));
+            }
+            for win in &result.wins {
+                msgs.push(json!({
+                    "type": "hora",
+                    "actor": win.player,
+                    "target": win.from,
+                    "pai": to_mjai_pai(win.pai),
+                    "ura_markers": to_mjai_list(ura),
+                    "deltas": result.deltas,
+                    "scores": points,
+                }));
+            }
+            msgs.push(json!({"type": "end_kyoku"}));
+            msgs
+        }
+        jsif::GameEvent::GameEnd { result } => {
+            let mut standings = result.standings.clone();
+            standings.sort_by_key(|s| s.player);
+            let scores: Vec<i32> = standings.iter().map(|s| s.point).collect();
+            vec![json!({"type": "end_game", "scores": scores})]
+        }
+        jsif::GameEvent::Chat { .. } => vec![],
+    }
+}
+
+fn field_pai(msg: &Value, key: &str) -> Result<i32> {
+    let s = msg[key]
+        .as_str()
+        .ok_or_else(|| anyhow!("No {} in {}", key, msg))?;
+
+    from_mjai_pai(s)
+}
+
+fn field_pais(msg: &Value, key: &str) -> Result<Vec<i32>> {
+    let list = msg[key]
+        .as_array()
+        .ok_or_else(|| anyhow!("No {} in {}", key, msg))?;
+
+    list.iter()
+        .map(|x| {
+            let s = x.as_str().ok_or_else(|| anyhow!("Invalid pai: {}", x))?;
+            from_mjai_pai(s)
+        })
+        .collect()
+}
+
+// a mjai response to the action (reach is handled by the caller)
+pub fn to_action(msg: &Value, view: &jsif::LocalView, player: u32) -> Result<jsif::Action> {
+    let mtype = msg["type"].as_str().unwrap_or("none");
+    let action = match mtype {
+        "none" => {
+            if view.common.phase == jsif::GamePhase::ShowResult {
+                jsif::Action::Next
+            } else {
+                jsif::Action::Skip
+            }
+        }
+        "dahai" => {
+            let pai = field_pai(msg, "pai")?;
+            // local index 0 is self
+            let hand = &view.local.hands[0];
+            let draw = view.local.draws[0];
+            let tsumogiri = msg["tsumogiri"].as_bool().unwrap_or(false);
+            let index = if draw == pai && (tsumogiri || !hand.contains(&pai)) {
+                hand.len()
+            } else {
+                hand.iter()
+                    .position(|&x| x == pai)
+                    .ok_or_else(|| anyhow!("Not in hand: {}", msg))?
+            };
+            jsif::Action::Discard(index as i32)
+        }
+        "hora" => {
+            if view.common.turn == player && view.common.phase == jsif::GamePhase::WaitAction {
+                jsif::Action::Tsumo
+            } else {
+                jsif::Action::Ron
+            }
+        }
+        "ryukyoku" => jsif::Action::KyushuKyuhai,
+        "chi" => {
+            let mut codes = field_pais(msg, "consumed")?;
+            codes.push(field_pai(msg, "pai")?);
+            let lowest = codes.iter().map(|&x| jsif::pai_code(x)).min().unwrap();
+            jsif::Action::Chi(lowest)
+        }
+        "pon" => jsif::Action::Pon,
+        "daiminkan" => jsif::Action::BigKan,
+        "kakan" => jsif::Action::SmallKan(jsif::pai_code(field_pai(msg, "pai")?)),
+        "ankan" => {
+            let consumed = field_pais(msg, "consumed")?;
+            ensure!(!consumed.is_empty(), "Invalid ankan: {}", msg);
+            jsif::Action::BlindKan(jsif::pai_code(consumed[0]))
+        }
+        _ => bail!("Unknown message: {}", msg),
+    };
+
+    Ok(action)
+}
+
+// for connect, read and write (a stuck bot is an error)
+const BOT_TIMEOUT: Duration = Duration::from_secs(if cfg!(test) { 1 } else { 10 });
+
+// response lines from the bot
+enum BotReader {
+    // with the socket read timeout
+    Tcp(BufReader<TcpStream>),
+    // read by a thread for the deadline (pipes have no timeout)
+    Pipe(Receiver<io::Result<String>>),
+}
+
+impl BotReader {
+    // empty at the end
+    fn read_line(&mut self) -> Result<String> {
+        match self {
+            Self::Tcp(reader) => {
+                let mut line = String::new();
+                reader
+                    .read_line(&mut line)
+                    .context("No response from the bot")?;
+                Ok(line)
+            }
+            Self::Pipe(lines) => match lines.recv_timeout(BOT_TIMEOUT) {
+                Ok(line) => Ok(line?),
+                Err(RecvTimeoutError::Timeout) => bail!("No response from the bot"),
+                // the thread ends at EOF
+                Err(RecvTimeoutError::Disconnected) => Ok(String::new()),
+            },
+        }
+    }
+}
+
+// bot process or connection
+pub struct MjaiBot {
+    reader: BotReader,
+    writer: Box<dyn Write + Send>,
+    child: Option<Child>,
+}
+
+impl MjaiBot {
+    // talk over stdin/stdout
+    pub fn spawn(program: &str, args: &[String]) -> Result<Self> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start {}", program))?;
+        let writer = child.stdin.take().unwrap();
+        let mut reader = BufReader::new(child.stdout.take().unwrap());
+        // until the process is killed on drop
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {
+                    if tx.send(Ok(line)).is_err() {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            reader: BotReader::Pipe(rx),
+            writer: Box::new(writer),
+            child: Some(child),
+        })
+    }
+
+    // talk over a local TCP socket
+    pub fn connect(addr: &str) -> Result<Self> {
+        // the first address to accept
+        let stream = addr
+            .to_socket_addrs()
+            .with_context(|| format!("Invalid address {}", addr))?
+            .find_map(|a| TcpStream::connect_timeout(&a, BOT_TIMEOUT).ok())
+            .with_context(|| format!("Failed to connect {}", addr))?;
+        // small messages back and forth
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(BOT_TIMEOUT))?;
+        stream.set_write_timeout(Some(BOT_TIMEOUT))?;
+        let reader = BufReader::new(stream.try_clone()?);
+
+        Ok(Self {
+            reader: BotReader::Tcp(reader),
+            writer: Box::new(stream),
+            child: None,
+        })
+    }
+
+    // send a message and wait for the response
+    pub fn send(&mut self, msg: &Value) -> Result<Value> {
+        // one write for a line
+        self.writer.write_all(format!("{msg}\n").as_bytes())?;
+        self.writer.flush()?;
+
+        let line = self.reader.read_line()?;
+        ensure!(!line.is_empty(), "Bot closed the connection");
+
+        Ok(serde_json::from_str(line.trim())?)
+    }
+}
+
+impl Drop for MjaiBot {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// how to start a bot
+#[derive(Debug, Clone)]
+pub enum BotTarget {
+    // program and args
+    Command(String, Vec<String>),
+    // host:port
+    Tcp(String),
+}
+
+impl BotTarget {
+    // "tcp:HOST:PORT" or a command line
+    pub fn parse(s: &str) -> Result<Self> {
+        if let Some(addr) = s.strip_prefix("tcp:") {
+            return Ok(Self::Tcp(addr.to_string()));
+        }
+        let mut args = s.split_whitespace().map(|x| x.to_string());
+        let program = args.next().ok_or_else(|| anyhow!("Empty bot command"))?;
+
+        Ok(Self::Command(program, args.collect()))
+    }
+
+    pub fn start(&self) -> Result<MjaiBot> {
+        match self {
+            Self::Command(program, args) => MjaiBot::spawn(program, args),
+            Self::Tcp(addr) => MjaiBot::connect(addr),
+        }
+    }
+}
+
+// a bot seated at the game
+pub struct MjaiPlayer {
+    bot: MjaiBot,
+    // for logs
+    room: u64,
+    player: u32,
+    // the last event seq sent
+    since: u64,
+    // the response to the last message
+    last: Option<Value>,
+}
+
+impl MjaiPlayer {
+    pub fn new(bot: MjaiBot, room: u64, player: u32) -> Self {
+        Self {
+            bot,
+            room,
+            player,
+            since: 0,
+            last: None,
+        }
+    }
+
+    // for Game::get_events
+    pub fn since(&self) -> u64 {
+        self.since
+    }
+
+    // send new events to the bot and return its action if needed
+    pub fn step(
+        &mut self,
+        events: &[jsif::Event],
+        view: &jsif::LocalView,
+    ) -> Result<Option<jsif::Action>> {
+        if self.since == 0 && !events.is_empty() {
+            let count = view.common.player_count;
+            let names: Vec<String> = (0..count).map(|p| format!("Player{p}")).collect();
+            let msg = json!({"type": "start_game", "id": self.player, "names": names});
+            self.last = Some(self.bot.send(&msg)?);
+        }
+        for e in events {
+            for msg in to_mjai_messages(&e.event) {
+                // the own reach is already sent by decide()
+                if msg["type"] == "reach" && msg["actor"] == self.player {
+                    continue;
+                }
+                self.last = Some(self.bot.send(&msg)?);
+            }
+            self.since = e.seq;
+        }
+
+        let actions = &view.local.actions;
+        if actions.is_empty() {
+            return Ok(None);
+        }
+
+        let action = match self.last.take() {
+            Some(msg) => match self.decide(&msg, view) {
+                Ok(action) => Some(action),
+                Err(err) => {
+                    log::warn!(room = self.room, seat = self.player; "Invalid mjai decision: {err:#}");
+                    None
+                }
+            },
+            None => None,
+        };
+        let action = match action {
+            Some(action) if actions.contains(&action) => action,
+            // invalid or no decision
+            _ => cpu::default_action(view),
+        };
+
+        Ok(Some(action))
+    }
+
+    fn decide(&mut self, msg: &Value, view: &jsif::LocalView) -> Result<jsif::Action> {
+        if msg["type"] != "reach" {
+            return to_action(msg, view, self.player);
+        }
+
+        // confirm reach and get the discard
+        let reach = json!({"type": "reach", "actor": self.player});
+        let msg = self.bot.send(&reach)?;
+        match to_action(&msg, view, self.player)? {
+            jsif::Action::Discard(index) => Ok(jsif::Action::Reach(index)),
+            _ => bail!("Discard is required after reach: {}", msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mjgame::Game;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn pai_conversion() {
+        for code in 0..34 {
+            assert_eq!(code, from_mjai_pai(&to_mjai_pai(code)).unwrap());
+        }
+        let red = 13 | jsif::PAI_RED;
+        assert_eq!("5pr", to_mjai_pai(red));
+        assert_eq!(red, from_mjai_pai("5pr").unwrap());
+        assert_eq!("C", to_mjai_pai(33));
+        assert_eq!("?", to_mjai_pai(-1));
+        assert!(from_mjai_pai("4mr").is_err());
+        assert!(from_mjai_pai("0m").is_err());
+        assert!(from_mjai_pai("?").is_err());
+    }
+
+    #[test]
+    fn call_messages() {
+        let meld = jsif::Meld {
+            mtype: jsif::MeldType::Chi,
+            pai: 11,
+            from: 3,
+            red: 1,
+        };
+        let event = jsif::GameEvent::Call {
+            player: 0,
+            pai: 12,
+            meld,
+        };
+        let msgs = to_mjai_messages(&event);
+        assert_eq!(
+            json!({"type": "chi", "actor": 0, "target": 3, "pai": "4p", "consumed": ["3p", "5pr"]}),
+            msgs[0]
+        );
+    }
+
+    #[test]
+    fn bot_timeout() {
+        // connected but no response
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            while BufReader::new(&stream).read_line(&mut line).unwrap_or(0) > 0 {}
+        });
+        let mut bot = MjaiBot::connect(&addr).unwrap();
+        assert!(bot.send(&json!({"type": "none"})).is_err());
+        drop(bot);
+        server.join().unwrap();
+
+        // a process with no output
+        let mut bot = MjaiBot::spawn("sleep", &["30".to_string()]).unwrap();
+        assert!(bot.send(&json!({"type": "none"})).is_err());
+    }
+
+    // tsumogiri only
+    fn stub_bot(stream: TcpStream) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut id = -1;
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap() > 0 {
+            let msg: Value = serde_json::from_str(&line).unwrap();
+            line.clear();
+            let response = match msg["type"].as_str().unwrap() {
+                "start_game" => {
+                    id = msg["id"].as_i64().unwrap();
+                    json!({"type": "none"})
+                }
+                "tsumo" if msg["actor"] == id => json!({
+                    "type": "dahai",
+                    "actor": id,
+                    "pai": msg["pai"],
+                    "tsumogiri": true,
+                }),
+                _ => json!({"type": "none"}),
+            };
+            writer
+                .write_all(format!("{response}\n").as_bytes())
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn own_reach_once() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 0 {
+                let msg: Value = serde_json::from_str(&line).unwrap();
+                line.clear();
+                tx.send(msg["type"].as_str().unwrap().to_string()).unwrap();
+                writer.write_all(b"{\"type\":\"none\"}\n").unwrap();
+            }
+        });
+
+        let game = Game::new(Default::default()).unwrap();
+        let mut player = MjaiPlayer::new(MjaiBot::connect(&addr).unwrap(), 0, 0);
+        let reach = |p| jsif::GameEvent::Discard {
+            player: p,
+            pai: 0,
+            tsumogiri: false,
+            reach: true,
+        };
+        let events = vec![
+            jsif::Event {
+                seq: 1,
+                event: reach(0),
+            },
+            jsif::Event {
+                seq: 2,
+                event: reach(1),
+            },
+        ];
+        let view = game.get_view(0).unwrap();
+        // no decision by the bot
+        let action = player.step(&events, &view).unwrap();
+        assert!(action.is_some_and(|a| view.local.actions.contains(&a)));
+        drop(player);
+        server.join().unwrap();
+
+        let sent: Vec<String> = rx.iter().collect();
+        assert_eq!(vec!["start_game", "dahai", "reach", "dahai"], sent);
+    }
+
+    #[test]
+    fn play_with_stub_bots() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut handles = vec![];
+            for _ in 0..4 {
+                let (stream, _) = listener.accept().unwrap();
+                handles.push(thread::spawn(move || stub_bot(stream)));
+            }
+            handles
+        });
+
+        let rule = jsif::RuleConfig {
+            round_max: 1,
+            ..Default::default()
+        };
+        let game = Game::new(rule).unwrap();
+        let mut players: Vec<MjaiPlayer> = (0..4)
+            .map(|p| MjaiPlayer::new(MjaiBot::connect(&addr).unwrap(), 0, p))
+            .collect();
+        let step = |player: &mut MjaiPlayer| {
+            let p = player.player;
+            let events = game.get_events(p, player.since()).unwrap();
+            let view = game.get_view(p).unwrap();
+            match player.step(&events, &view).unwrap() {
+                Some(action) => game.action(p, action).is_ok(),
+                None => false,
+            }
+        };
+        // each round needs < 200 steps
+        for _ in 0..10000 {
+            if game.phase() == jsif::GamePhase::GameResult {
+                break;
+            }
+            let mut done = false;
+            for player in &mut players {
+                done |= step(player);
+            }
+            assert!(done);
+        }
+        assert_eq!(jsif::GamePhase::GameResult, game.phase());
+
+        // all events are sent
+        for player in &mut players {
+            step(player);
+            assert_eq!(game.get_events(0, 0).unwrap().len() as u64, player.since());
+        }
+        drop(players);
+        for handle in server.join().unwrap() {
+            handle.join().unwrap();
+        }
+    }
+}
@@ -1,6 +1,12 @@
+use crate::tenhou;
 use anyhow::{ensure, Result};
-use game::{jsif, mjsys};
+use game::mjsys::yaku::{Yaku, Yakuman};
+use game::{jsif, mjsys, mjsys::shanten};
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::sync::RwLock;
 
 /*
@@ -11,9 +17,11 @@ NAP: Non Active Player
 
 1. After draw
 * AP can:
-** Trash one -> 2
+** Trash one (with reach) -> 2
 ** Kan (open/blind) -> 1
+** Kan (added) -> 2 (Ron only, chankan)
 ** Tsumo -> 3
+** Kyushu kyuhai (the first draw) -> 3
 * Any NAPs can do nothing.
 
 2. After trash
@@ -23,10 +31,14 @@ NAP: Non Active Player
 ** Kan -> 1
 ** Ron -> 3
 ** No Reaction -> 1
+* Abortive draws (four winds, four reach, four kans, triple ron) -> 3
 
 3. Result
 * Wait for response from all players.
 * Go to next or finish game.
+** Parent continues if parent wins or tenpai (hon + 1).
+** Otherwise the next player becomes parent (hon + 1 if draw).
+** Finish at the last round, or if anyone's point < 0.
 
 */
 
@@ -34,39 +46,267 @@ NAP: Non Active Player
 pub struct Game(RwLock<GameState>);
 
 // main game status control data
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct GameState {
     // common view to all players as is
     common: jsif::CommonState,
     // hidden or player-dependent view
     internal: InternalState,
+    rule: jsif::RuleConfig,
+    // (discard count, view) snapshots for spectator delay
+    #[serde(skip)]
+    spectator: VecDeque<(u64, jsif::LocalView)>,
+    events: EventLog,
+    // fixed seed for simulation (thread_rng if None)
+    #[serde(skip)]
+    rng: Option<StdRng>,
+}
+
+// all events in the game
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct EventLog(Vec<jsif::Event>);
+
+impl EventLog {
+    fn push(&mut self, event: jsif::GameEvent) {
+        let seq = self.0.len() as u64 + 1;
+        self.0.push(jsif::Event { seq, event });
+    }
+
+    // seq of the last game event (0 if none)
+    fn last_game_seq(&self) -> u64 {
+        self.0
+            .iter()
+            .rev()
+            .find(|e| !matches!(e.event, jsif::GameEvent::Chat { .. }))
+            .map_or(0, |e| e.seq)
+    }
+}
+
+// who sees the local state
+#[derive(Debug, Clone, Copy)]
+enum Viewer {
+    Player(u32),
+    // with or without all hands, from player 0 side
+    Spectator(bool),
+}
+
+// wang pai count
+const YAMA2_COUNT: usize = 14;
+// wang pai layout: dora indicators, ura indicators, lingshang
+const YAMA2_DORA: usize = 0;
+const YAMA2_URA: usize = 5;
+const YAMA2_LINGSHANG: usize = 10;
+const KAN_MAX: usize = 4;
+// total noten bappu
+const NOTEN_PAYMENT: i32 = 3000;
+const REACH_DEPOSIT: i32 = 1000;
+const HON_RON: i32 = 300;
+const HON_TUMO: i32 = 100;
+// yao kinds for kyushu kyuhai
+const KYUSHU_KINDS: usize = 9;
+// rule limits
+const ROUND_MAX: u32 = 4;
+const SPECTATOR_DELAY_MAX: u32 = 200;
+// seconds
+const TIME_LIMIT_MAX: u32 = 600;
+// for spectator_hands, otherwise a player can see all hands almost live
+const SPECTATOR_DELAY_MIN: u32 = 16;
+
+// concealed pais and melds to mjsys::Hand (for shanten and finish patterns)
+pub fn to_mjhand(hand: &[i32], melds: &[jsif::Meld]) -> mjsys::Hand {
+    let mut mjhand: mjsys::Hand = Default::default();
+    for &pai in hand {
+        mjhand.bucket[jsif::pai_code(pai) as usize] += 1;
+    }
+    for m in melds {
+        let mtype = match m.mtype {
+            jsif::MeldType::Chi => mjsys::MianziType::OrderedChi,
+            jsif::MeldType::Pon => mjsys::MianziType::SamePon,
+            jsif::MeldType::BlindKan => mjsys::MianziType::SameKanBlind,
+            jsif::MeldType::OpenKan | jsif::MeldType::AddedKan => mjsys::MianziType::SameKanOpen,
+        };
+        mjhand.mianzi_list.push(mjsys::Mianzi {
+            mtype,
+            pai: m.pai as u8,
+        });
+    }
+
+    mjhand
 }
 
 // player-dependent data (managed by system)
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 struct InternalState {
     yama: Vec<i32>,
-    // wang pai
+    // wang pai (YAMA2_COUNT)
     yama2: Vec<i32>,
     points: Vec<i32>,
     hands: Vec<Vec<i32>>,
     draws: Vec<Option<i32>>,
     actions: Vec<Vec<jsif::Action>>,
+    // NAP responses to the last discard
+    reactions: Vec<Option<jsif::Action>>,
+    // (player, pai)
+    last_discard: Option<(u32, i32)>,
+    // reach declared but the discard is not accepted yet
+    reach_pending: Option<u32>,
+    double_reach: Vec<bool>,
+    ippatsu: Vec<bool>,
+    // before the first discard of each player
+    first_turn: Vec<bool>,
+    // any call in this round
+    interrupted: bool,
+    // the current draw is from wang pai
+    lingshang: bool,
+    // last_discard is an added kan (Ron only)
+    chankan: bool,
+    // missed Ron (cleared at the next discard unless reach)
+    furiten: Vec<bool>,
+    // kan count for each player
+    kans: Vec<u32>,
+    // total discards in the game
+    discard_count: u64,
 }
 
 impl Game {
-    pub fn new() -> Result<Game> {
+    pub fn new(rule: jsif::RuleConfig) -> Result<Game> {
+        Self::with_rng(rule, None)
+    }
+
+    // reproducible walls
+    pub fn with_seed(rule: jsif::RuleConfig, seed: u64) -> Result<Game> {
+        Self::with_rng(rule, Some(StdRng::seed_from_u64(seed)))
+    }
+
+    fn with_rng(rule: jsif::RuleConfig, rng: Option<StdRng>) -> Result<Game> {
         let mut state = GameState::new();
-        // TODO: pass rule config
+        state.rule = rule;
+        state.rng = rng;
         state.init()?;
+        state.record_spectator();
 
         // After this, it is necessary to take a lock for GameState access
         Ok(Game(RwLock::new(state)))
     }
 
+    // full state (with the wall) for persistence
+    pub fn save(&self) -> serde_json::Value {
+        serde_json::to_value(&*self.0.read().unwrap()).unwrap()
+    }
+
+    pub fn load(value: serde_json::Value) -> Result<Game> {
+        let mut state: GameState = serde_json::from_value(value)?;
+        GameState::validate_rule(&state.rule)?;
+        state.check()?;
+        state.verify()?;
+        // spectator delay restarts from here (no hands until it passes)
+        state.record_spectator();
+
+        Ok(Game(RwLock::new(state)))
+    }
+
+    pub fn rule(&self) -> jsif::RuleConfig {
+        self.0.read().unwrap().rule.clone()
+    }
+
+    pub fn phase(&self) -> jsif::GamePhase {
+        self.0.read().unwrap().common.phase
+    }
+
+    // pai and point conservation
+    pub fn verify(&self) -> Result<()> {
+        self.0.read().unwrap().verify()
+    }
+
+    // events after the seq number, hidden pais are removed
+    pub fn get_events(&self, player: u32, since: u64) -> Result<Vec<jsif::Event>> {
+        let state = self.0.read().unwrap();
+        ensure!(
+            player < state.common.player_count,
+            "Invalid player: {}",
+            player
+        );
+
+        let result = state
+            .events
+            .0
+            .iter()
+            .skip(since as usize)
+            .map(|e| jsif::Event {
+                seq: e.seq,
+                event: Self::filter_event(&e.event, player),
+            })
+            .collect();
+
+        Ok(result)
+    }
+
+    // stored in the event list (player None for spectators)
+    pub fn chat(&self, player: Option<u32>, body: jsif::ChatBody) -> Result<()> {
+        let state = &mut *self.0.write().unwrap();
+        if let Some(player) = player {
+            ensure!(
+                player < state.common.player_count,
+                "Invalid player: {}",
+                player
+            );
+        }
+        state.events.push(jsif::GameEvent::Chat { player, body });
+
+        Ok(())
+    }
+
+    // chat events after the seq number (public)
+    pub fn get_chat(&self, since: u64) -> Vec<jsif::Event> {
+        let state = self.0.read().unwrap();
+
+        state
+            .events
+            .0
+            .iter()
+            .skip(since as usize)
+            .filter(|e| matches!(e.event, jsif::GameEvent::Chat { .. }))
+            .cloned()
+            .collect()
+    }
+
+    // tenhou.net/6 JSON log of the finished game
+    pub fn get_log(&self, title: &str) -> Result<serde_json::Value> {
+        let state = self.0.read().unwrap();
+        ensure!(
+            state.common.phase == jsif::GamePhase::GameResult,
+            "Game is not finished"
+        );
+
+        Ok(tenhou::export(title, &state.rule, &state.events.0))
+    }
+
+    fn filter_event(event: &jsif::GameEvent, player: u32) -> jsif::GameEvent {
+        let mut event = event.clone();
+        match &mut event {
+            jsif::GameEvent::RoundStart {
+                hands, yama, yama2, ..
+            } => {
+                for (p, hand) in hands.iter_mut().enumerate() {
+                    if p != player as usize {
+                        hand.clear();
+                    }
+                }
+                yama.clear();
+                yama2.clear();
+            }
+            jsif::GameEvent::Draw { player: p, pai, .. } if *p != player => *pai = -1,
+            _ => {}
+        }
+
+        event
+    }
+
     pub fn get_view(&self, player: u32) -> Result<jsif::LocalView> {
         // read lock and (common, internal) <- state
-        let GameState { common, internal } = &*self.0.read().unwrap();
+        let GameState {
+            common, internal, ..
+        } = &*self.0.read().unwrap();
         ensure!(player < common.player_count, "Invalid player: {}", player);
 
         // result struct for json output
@@ -74,44 +314,75 @@ impl Game {
             // copy common field
             common: common.clone(),
             // convert from internal
-            local: Self::convert_to_local_state(common, internal, player),
+            local: Self::convert_to_local_state(common, internal, Viewer::Player(player)),
         };
 
         Ok(result)
     }
 
+    // public information (and all hands if allowed) with the rule delay
+    // local view index = player index
+    pub fn get_spectator_view(&self) -> jsif::LocalView {
+        let state = self.0.read().unwrap();
+
+        state.spectator.front().unwrap().1.clone()
+    }
+
     fn convert_to_local_state(
         common: &jsif::CommonState,
         internal: &InternalState,
-        player: u32,
+        viewer: Viewer,
     ) -> jsif::LocalState {
         let mut local: jsif::LocalState = Default::default();
+        let player = match viewer {
+            Viewer::Player(player) => player,
+            Viewer::Spectator(_) => 0,
+        };
 
+        let count = common.player_count;
         for i in 0..4 {
-            // i = global player index
-            // p = local player index
-            let p = (player + i) % 4;
+            // i = local player index (self, shimocha, ...)
+            // p = global player index
+            let p = (player + i) % count;
 
             let ius = i as usize;
             let pus = p as usize;
 
-            if p < common.player_count {
-                // hand
+            // empty seats after player_count
+            if i < count {
                 local.points[ius] = internal.points[pus];
-                local.hands[ius] = internal.hands[pus].clone();
-                for pai in &*local.hands[ius] {
-                    let code = *pai as u8;
+                local.hand_counts[ius] =
+                    (internal.hands[pus].len() + internal.draws[pus].iter().count()) as u32;
+                let visible = match viewer {
+                    Viewer::Player(_) => ius == 0,
+                    Viewer::Spectator(hands) => hands,
+                };
+                // self, or revealed by the round result
+                let (hand, draw) = if visible {
+                    (&internal.hands[pus][..], internal.draws[pus])
+                } else {
+                    match &common.result {
+                        Some(result) => (&result.hands[pus][..], None),
+                        None => (&[][..], None),
+                    }
+                };
+                // hand
+                local.hands[ius] = hand.to_vec();
+                for &pai in hand {
+                    let code = jsif::pai_code(pai) as u8;
                     local.hands_str[ius].push(mjsys::to_human_readable_string(code).unwrap());
                 }
                 // draw
-                local.draws[ius] = internal.draws[pus].unwrap_or(-1);
-                local.draws_str[ius] = match internal.draws[pus] {
-                    Some(pai) => mjsys::to_human_readable_string(pai as u8).unwrap(),
+                local.draws[ius] = draw.unwrap_or(-1);
+                local.draws_str[ius] = match draw {
+                    Some(pai) => {
+                        mjsys::to_human_readable_string(jsif::pai_code(pai) as u8).unwrap()
+                    }
                     None => "".to_string(),
                 };
                 // action
-                if pus == 0 {
-                    local.actions = internal.actions[ius].clone();
+                if ius == 0 && matches!(viewer, Viewer::Player(_)) {
+                    local.actions = internal.actions[pus].clone();
                 }
             } else {
                 // empty seat
@@ -124,10 +395,78 @@ impl Game {
         local
     }
 
-    #[allow(dead_code)]
-    pub fn action(&mut self) {
-        let mut _state = self.0.write().unwrap();
-        // ...
+    pub fn action(&self, player: u32, action: jsif::Action) -> Result<()> {
+        // write lock
+        Self::action_locked(&mut self.0.write().unwrap(), player, action)
+    }
+
+    // for turn timers: the decision (seq of the last game event, not chat)
+    // and whether each player has actions
+    pub fn decision(&self) -> (u64, Vec<bool>) {
+        let state = self.0.read().unwrap();
+        let pending = state.internal.actions.iter().map(|a| !a.is_empty());
+
+        (state.events.last_game_seq(), pending.collect())
+    }
+
+    // action only if the decision is not changed (for time outs)
+    pub fn action_since(&self, player: u32, seq: u64, action: jsif::Action) -> Result<()> {
+        // write lock
+        let state = &mut *self.0.write().unwrap();
+        ensure!(
+            state.events.last_game_seq() == seq,
+            "Outdated decision: {}",
+            seq
+        );
+        Self::action_locked(state, player, action)
+    }
+
+    fn action_locked(state: &mut GameState, player: u32, action: jsif::Action) -> Result<()> {
+        ensure!(
+            player < state.common.player_count,
+            "Invalid player: {}",
+            player
+        );
+        ensure!(
+            state.internal.actions[player as usize].contains(&action),
+            "Invalid action: {:?}",
+            action
+        );
+
+        state.action(player, action)?;
+        state.record_spectator();
+
+        Ok(())
+    }
+
+    // end the current round by the server admin
+    // exhaustive draw (with noten payments) or abort (Canceled)
+    pub fn force_end(&self, draw: bool) -> Result<()> {
+        // write lock
+        let state = &mut *self.0.write().unwrap();
+        ensure!(
+            matches!(
+                state.common.phase,
+                jsif::GamePhase::WaitAction | jsif::GamePhase::WaitReaction
+            ),
+            "Not in a round: {:?}",
+            state.common.phase
+        );
+
+        // reach declaration is not accepted
+        if let Some(p) = state.internal.reach_pending.take() {
+            state.common.reach[p as usize] = false;
+        }
+        let count = state.common.player_count as usize;
+        state.internal.reactions = vec![None; count];
+        if draw {
+            state.exhaustive_draw();
+        } else {
+            state.abort(jsif::RoundEnd::Canceled, &[]);
+        }
+        state.record_spectator();
+
+        Ok(())
     }
 }
 
@@ -142,51 +481,268 @@ impl GameState {
                 wind: 0,
                 parent: 0,
                 hon: 0,
+                deposit: 0,
+                yama_rest: 0,
+                dora: Vec::new(),
+                rivers: Vec::new(),
+                melds: Vec::new(),
+                reach: Vec::new(),
+                result: None,
+                final_result: None,
+                rule: Default::default(),
             },
             internal: Default::default(),
+            rule: Default::default(),
+            spectator: VecDeque::new(),
+            events: Default::default(),
+            rng: None,
+        }
+    }
+
+    // also for restored states (no panic)
+    fn check(&self) -> Result<()> {
+        let (common, internal) = (&self.common, &self.internal);
+        let count = common.player_count;
+
+        ensure!((2..=4).contains(&count), "Invalid player_count: {}", count);
+        ensure!(count == self.rule.player_count, "player_count mismatch");
+        ensure!(common.round_max <= 4, "Invalid round_max");
+        ensure!(common.wind < 4, "Invalid wind");
+        ensure!(common.parent < count, "Invalid parent");
+        ensure!(common.turn < count, "Invalid turn");
+        ensure!(internal.yama2.len() == YAMA2_COUNT, "Invalid yama2");
+        ensure!(
+            common.yama_rest as usize == internal.yama.len(),
+            "Invalid yama_rest"
+        );
+        ensure!(common.dora.len() <= KAN_MAX + 1, "Invalid dora");
+        // for each player
+        let count = count as usize;
+        for (name, len) in [
+            ("rivers", common.rivers.len()),
+            ("melds", common.melds.len()),
+            ("reach", common.reach.len()),
+            ("points", internal.points.len()),
+            ("hands", internal.hands.len()),
+            ("draws", internal.draws.len()),
+            ("actions", internal.actions.len()),
+            ("reactions", internal.reactions.len()),
+            ("double_reach", internal.double_reach.len()),
+            ("ippatsu", internal.ippatsu.len()),
+            ("first_turn", internal.first_turn.len()),
+            ("furiten", internal.furiten.len()),
+            ("kans", internal.kans.len()),
+        ] {
+            ensure!(len == count, "Invalid {} length: {}", name, len);
+        }
+
+        Ok(())
+    }
+
+    fn verify(&self) -> Result<()> {
+        let (common, internal, rule) = (&self.common, &self.internal, &self.rule);
+        let count = common.player_count as usize;
+
+        // the called pai is in the river, chi is not counted for each kind
+        let mut bucket = [0u32; mjsys::PAI_COUNT];
+        let mut total = 0;
+        let mut add = |pai: i32, n: u32, kind: bool| {
+            total += n;
+            if kind {
+                bucket[jsif::pai_code(pai) as usize] += n;
+            }
+        };
+        internal.yama.iter().for_each(|&x| add(x, 1, true));
+        internal.yama2.iter().for_each(|&x| add(x, 1, true));
+        for p in 0..count {
+            let hand = &internal.hands[p];
+            hand.iter().for_each(|&x| add(x, 1, true));
+            internal.draws[p].iter().for_each(|&x| add(x, 1, true));
+            common.rivers[p].iter().for_each(|&x| add(x, 1, true));
+            for m in &common.melds[p] {
+                match m.mtype {
+                    jsif::MeldType::Chi => add(m.pai, 2, false),
+                    jsif::MeldType::Pon => add(m.pai, 2, true),
+                    jsif::MeldType::OpenKan | jsif::MeldType::AddedKan => add(m.pai, 3, true),
+                    jsif::MeldType::BlindKan => add(m.pai, 4, true),
+                }
+            }
+            // 14 before the discard
+            let size = hand.len() + internal.draws[p].iter().count() + common.melds[p].len() * 3;
+            ensure!(
+                size == 13 || size == 14,
+                "Invalid hand size: player {}, {}",
+                p,
+                size
+            );
+        }
+        let expected = if count == 3 { 108 } else { 136 };
+        ensure!(total == expected, "Invalid pai count: {}", total);
+        for (code, &n) in bucket.iter().enumerate() {
+            ensure!(n <= 4, "Too many pais: code {}, {}", code, n);
+        }
+
+        let start_total = rule.start_point * count as i32;
+        // left over without deposit_to_top
+        let deposit = common.deposit as i32 * REACH_DEPOSIT;
+        if let Some(result) = &common.final_result {
+            let points: i32 = result.standings.iter().map(|s| s.point).sum();
+            let scores: i32 = result.standings.iter().map(|s| s.score).sum();
+            ensure!(
+                points + deposit == start_total,
+                "Invalid final point sum: {} + {}",
+                points,
+                deposit
+            );
+            ensure!(
+                scores + deposit == 0,
+                "Invalid final score sum: {} + {}",
+                scores,
+                deposit
+            );
+        } else {
+            let points: i32 = internal.points.iter().sum();
+            ensure!(
+                points + deposit == start_total,
+                "Invalid point sum: {} + {}",
+                points,
+                deposit
+            );
+        }
+
+        Ok(())
+    }
+
+    // keep the latest view for each discard count within the delay
+    fn record_spectator(&mut self) {
+        let (common, internal) = (&self.common, &self.internal);
+        let count = internal.discard_count;
+
+        // the first view is shown before the delay passes, without hands
+        let first = match self.spectator.back() {
+            Some((last, _)) => self.spectator.len() == 1 && *last == count,
+            None => true,
+        };
+        let viewer = Viewer::Spectator(self.rule.spectator_hands && !first);
+        let view = jsif::LocalView {
+            common: common.clone(),
+            local: Game::convert_to_local_state(common, internal, viewer),
+        };
+        match self.spectator.back_mut() {
+            Some((last, last_view)) if *last == count => *last_view = view,
+            _ => self.spectator.push_back((count, view)),
+        }
+
+        let visible = count.saturating_sub(self.rule.spectator_delay as u64);
+        while self.spectator.len() > 1 && self.spectator[1].0 <= visible {
+            self.spectator.pop_front();
         }
     }
 
-    fn check(&self) {
-        let (common, _internal) = (&self.common, &self.internal);
+    fn validate_rule(rule: &jsif::RuleConfig) -> Result<()> {
+        ensure!(
+            (2..=4).contains(&rule.player_count),
+            "Invalid player_count: {}",
+            rule.player_count
+        );
+        ensure!(
+            rule.cpu_players <= rule.player_count,
+            "Invalid cpu_players: {}",
+            rule.cpu_players
+        );
+        ensure!(
+            (1..=ROUND_MAX).contains(&rule.round_max),
+            "Invalid round_max: {}",
+            rule.round_max
+        );
+        for (name, point) in [
+            ("start_point", rule.start_point),
+            ("return_point", rule.return_point),
+            ("target_point", rule.target_point),
+        ] {
+            ensure!(point > 0 && point % 100 == 0, "Invalid {}: {}", name, point);
+        }
+        ensure!(
+            rule.return_point >= rule.start_point,
+            "return_point must not be less than start_point"
+        );
+        ensure!(
+            rule.uma.len() == rule.player_count as usize,
+            "uma must have {} elements",
+            rule.player_count
+        );
+        ensure!(
+            rule.uma.iter().sum::<i32>() == 0,
+            "The sum of uma must be 0"
+        );
+        ensure!(
+            rule.spectator_delay <= SPECTATOR_DELAY_MAX,
+            "spectator_delay must be less than or equal to {}",
+            SPECTATOR_DELAY_MAX
+        );
+        ensure!(
+            !rule.spectator_hands || rule.spectator_delay >= SPECTATOR_DELAY_MIN,
+            "spectator_hands requires spectator_delay of at least {}",
+            SPECTATOR_DELAY_MIN
+        );
+        for (name, time) in [
+            ("time_limit", rule.time_limit),
+            ("time_extra", rule.time_extra),
+        ] {
+            ensure!(
+                time <= TIME_LIMIT_MAX,
+                "{} must be less than or equal to {}",
+                name,
+                TIME_LIMIT_MAX
+            );
+        }
+        ensure!(
+            rule.time_limit > 0 || rule.time_extra == 0,
+            "time_extra requires time_limit"
+        );
 
-        assert!(2 <= common.player_count && common.player_count <= 4);
-        assert!(common.round_max <= 4);
-        assert!(common.wind < 4);
-        assert!(common.parent < common.player_count);
-        assert!(common.turn < common.player_count);
+        Ok(())
     }
 
     fn init(&mut self) -> Result<()> {
+        Self::validate_rule(&self.rule)?;
         let (common, internal) = (&mut self.common, &mut self.internal);
 
-        // TODO: receive rule config and set
-        common.player_count = 4;
-        common.round_max = 4;
+        common.player_count = self.rule.player_count;
+        common.round_max = self.rule.round_max;
+        common.rule = self.rule.clone();
         common.turn = 0;
         common.wind = 0;
         common.parent = 0;
         common.hon = 0;
+        common.deposit = 0;
 
         internal.yama.clear();
         internal.yama2.clear();
         for _ in 0..common.player_count {
-            internal.points.push(25000);
+            common.rivers.push(vec![]);
+            common.melds.push(vec![]);
+            common.reach.push(false);
+            internal.points.push(self.rule.start_point);
             internal.hands.push(vec![]);
             internal.draws.push(None);
             internal.actions.push(vec![]);
+            internal.reactions.push(None);
+            internal.double_reach.push(false);
+            internal.ippatsu.push(false);
+            internal.first_turn.push(false);
+            internal.furiten.push(false);
+            internal.kans.push(0);
         }
 
         // init as tong 1 kyoku 0 hon start
         self.next_round(0, 0, 0);
 
-        self.check();
-
-        Ok(())
+        self.check()
     }
 
     fn next_round(&mut self, wind: u32, parent: u32, hon: u32) {
-        let (common, internal) = (&mut self.common, &mut self.internal);
+        let (common, internal, rule) = (&mut self.common, &mut self.internal, &self.rule);
 
         common.wind = wind;
         common.parent = parent;
@@ -196,12 +752,20 @@ impl GameState {
         // Create yama
         {
             let mut yama_tmp: Vec<i32> = vec![];
-            for _ in 0..4 {
+            for n in 0..4 {
                 // man, pin, so: 0, 1, 2
                 for kind in 0..3 {
                     // 1-9
                     for num in 1..=9 {
-                        yama_tmp.push(mjsys::encode(kind, num) as i32);
+                        // 3 players: 1m and 9m only
+                        if common.player_count == 3 && kind == 0 && (2..=8).contains(&num) {
+                            continue;
+                        }
+                        let mut pai = mjsys::encode(kind, num) as i32;
+                        if rule.red_five && n == 0 && num == 5 {
+                            pai |= jsif::PAI_RED;
+                        }
+                        yama_tmp.push(pai);
                     }
                 }
                 // zu: 3
@@ -209,12 +773,36 @@ impl GameState {
                     yama_tmp.push(mjsys::encode(3, num) as i32);
                 }
             }
-            // thread_local cryptographically secure PRNG
-            let mut rng = rand::thread_rng();
-            yama_tmp.shuffle(&mut rng);
+            match &mut self.rng {
+                Some(rng) => yama_tmp.shuffle(rng),
+                // thread_local cryptographically secure PRNG
+                None => yama_tmp.shuffle(&mut rand::thread_rng()),
+            }
 
+            // split wang pai from the opposite side
+            internal.yama2 = yama_tmp.drain(0..YAMA2_COUNT).collect();
             internal.yama = yama_tmp;
         }
+        common.dora = vec![internal.yama2[YAMA2_DORA]];
+        common.result = None;
+        for p in 0..common.player_count as usize {
+            common.rivers[p].clear();
+            common.melds[p].clear();
+            common.reach[p] = false;
+            internal.hands[p].clear();
+            internal.draws[p] = None;
+            internal.reactions[p] = None;
+            internal.double_reach[p] = false;
+            internal.ippatsu[p] = false;
+            internal.first_turn[p] = true;
+            internal.furiten[p] = false;
+            internal.kans[p] = 0;
+        }
+        internal.last_discard = None;
+        internal.chankan = false;
+        internal.reach_pending = None;
+        internal.interrupted = false;
+        internal.lingshang = false;
         // haipai
         {
             // 4 times
@@ -233,12 +821,377 @@ impl GameState {
             for i in 0..common.player_count {
                 let i = i as usize;
                 assert!(internal.hands[i].len() == 13);
-                internal.hands[i].sort_unstable();
+                Self::sort_hand(&mut internal.hands[i]);
             }
+            self.events.push(jsif::GameEvent::RoundStart {
+                wind,
+                parent,
+                hon,
+                deposit: common.deposit,
+                dora: common.dora[0],
+                points: internal.points.clone(),
+                hands: internal.hands.clone(),
+                yama: internal.yama.clone(),
+                yama2: internal.yama2.clone(),
+            });
             // parent draw
             self.draw();
         }
-        self.check();
+        self.check().unwrap();
+    }
+
+    fn action(&mut self, player: u32, action: jsif::Action) -> Result<()> {
+        match action {
+            jsif::Action::Discard(index) => self.discard(player, index as usize, false),
+            jsif::Action::Reach(index) => self.discard(player, index as usize, true),
+            jsif::Action::Tsumo => {
+                let pai = self.internal.draws[player as usize].unwrap();
+                self.win(&[player], player, pai, true);
+            }
+            jsif::Action::BlindKan(_) | jsif::Action::SmallKan(_) => self.self_kan(player, action),
+            jsif::Action::KyushuKyuhai => self.abort(jsif::RoundEnd::KyushuKyuhai, &[player]),
+            jsif::Action::Skip
+            | jsif::Action::Ron
+            | jsif::Action::Chi(_)
+            | jsif::Action::Pon
+            | jsif::Action::BigKan => self.reaction(player, action),
+            jsif::Action::Next => self.confirm_result(player),
+        }
+
+        Ok(())
+    }
+
+    // Active player discards a pai, then NAPs may react to it
+    fn discard(&mut self, player: u32, index: usize, reach: bool) {
+        let (common, internal) = (&mut self.common, &mut self.internal);
+        assert!(player == common.turn);
+
+        let p = player as usize;
+        let draw = internal.draws[p].take();
+        let hand = &mut internal.hands[p];
+        let tsumogiri = index >= hand.len();
+        let pai = if index < hand.len() {
+            let pai = hand.remove(index);
+            if let Some(draw) = draw {
+                hand.push(draw);
+                Self::sort_hand(hand);
+            }
+            pai
+        } else {
+            draw.unwrap()
+        };
+        common.rivers[p].push(pai);
+
+        // skipping Ron is valid until the next discard (forever if reach)
+        if !common.reach[p] {
+            internal.furiten[p] = false;
+        }
+        if reach {
+            common.reach[p] = true;
+            internal.double_reach[p] = internal.first_turn[p] && !internal.interrupted;
+            internal.reach_pending = Some(player);
+        } else {
+            internal.ippatsu[p] = false;
+        }
+        internal.first_turn[p] = false;
+        internal.lingshang = false;
+        internal.last_discard = Some((player, pai));
+        internal.discard_count += 1;
+        self.events.push(jsif::GameEvent::Discard {
+            player,
+            pai,
+            tsumogiri,
+            reach,
+        });
+
+        self.update_reactions();
+    }
+
+    // Create NAP action lists for the last discard
+    fn update_reactions(&mut self) {
+        let (from, pai) = self.internal.last_discard.unwrap();
+        let count = self.common.player_count;
+
+        let mut lists = vec![vec![]; count as usize];
+        for i in 1..count {
+            let p = (from + i) % count;
+            lists[p as usize] = self.reaction_list(p, from, pai);
+        }
+
+        let common = &mut self.common;
+        let internal = &mut self.internal;
+        common.phase = jsif::GamePhase::WaitReaction;
+        internal.actions = lists;
+        for r in &mut internal.reactions {
+            *r = None;
+        }
+
+        // nobody can react
+        if internal.actions.iter().all(|alist| alist.is_empty()) {
+            self.resolve_reactions();
+        }
+    }
+
+    fn reaction_list(&self, player: u32, from: u32, pai: i32) -> Vec<jsif::Action> {
+        let (common, internal) = (&self.common, &self.internal);
+        let p = player as usize;
+        let mut alist = vec![];
+
+        if !self.is_furiten(player) && self.calc_win(player, pai, false).is_some() {
+            alist.push(jsif::Action::Ron);
+        }
+        // no call for the last discard or an added kan
+        if !common.reach[p] && common.yama_rest > 0 && !internal.chankan {
+            let pai = jsif::pai_code(pai);
+            let hand: Vec<i32> = internal.hands[p]
+                .iter()
+                .map(|&x| jsif::pai_code(x))
+                .collect();
+            let same = hand.iter().filter(|&&x| x == pai).count();
+            if same >= 2 {
+                alist.push(jsif::Action::Pon);
+            }
+            if same >= 3 && self.kan_count() < KAN_MAX {
+                alist.push(jsif::Action::BigKan);
+            }
+            // the next player only
+            if player == (from + 1) % common.player_count && mjsys::is_num(pai as u8) {
+                let (_kind, num) = mjsys::decode(pai as u8);
+                for start in num.saturating_sub(2).max(1)..=num.min(7) {
+                    let start = pai - (num - start) as i32;
+                    let ok = (start..start + 3)
+                        .filter(|&x| x != pai)
+                        .all(|x| hand.contains(&x));
+                    if ok {
+                        alist.push(jsif::Action::Chi(start));
+                    }
+                }
+            }
+        }
+        if !alist.is_empty() {
+            alist.push(jsif::Action::Skip);
+        }
+
+        alist
+    }
+
+    fn reaction(&mut self, player: u32, action: jsif::Action) {
+        let internal = &mut self.internal;
+        let p = player as usize;
+
+        // missed Ron
+        if action != jsif::Action::Ron && internal.actions[p].contains(&jsif::Action::Ron) {
+            internal.furiten[p] = true;
+        }
+        internal.reactions[p] = Some(action);
+        internal.actions[p].clear();
+
+        // wait for all
+        if internal.actions.iter().all(|alist| alist.is_empty()) {
+            self.resolve_reactions();
+        }
+    }
+
+    fn resolve_reactions(&mut self) {
+        let (from, pai) = self.internal.last_discard.unwrap();
+        let count = self.common.player_count;
+
+        // in turn order from the discarded player
+        let order: Vec<u32> = (1..count).map(|i| (from + i) % count).collect();
+        let reactions = std::mem::replace(&mut self.internal.reactions, vec![None; count as usize]);
+
+        let rons: Vec<u32> = order
+            .iter()
+            .copied()
+            .filter(|&p| reactions[p as usize] == Some(jsif::Action::Ron))
+            .collect();
+        if !rons.is_empty() {
+            if rons.len() >= 3 && self.rule.abort_triple_ron {
+                self.abort(jsif::RoundEnd::TripleRon, &rons);
+            } else {
+                self.win(&rons, from, pai, false);
+            }
+            return;
+        }
+        // the added kan is not robbed
+        if std::mem::take(&mut self.internal.chankan) {
+            self.draw_lingshang();
+            return;
+        }
+
+        // the discard is accepted
+        if let Some(p) = self.internal.reach_pending.take() {
+            self.internal.points[p as usize] -= REACH_DEPOSIT;
+            self.internal.ippatsu[p as usize] = true;
+            self.common.deposit += 1;
+            self.events
+                .push(jsif::GameEvent::ReachAccepted { player: p });
+        }
+        if let Some(reason) = self.check_abort() {
+            self.abort(reason, &[]);
+            return;
+        }
+
+        // Pon/Kan has priority over Chi
+        let call = order
+            .iter()
+            .find_map(|&p| match reactions[p as usize] {
+                Some(a @ (jsif::Action::Pon | jsif::Action::BigKan)) => Some((p, a)),
+                _ => None,
+            })
+            .or_else(|| {
+                order.iter().find_map(|&p| match reactions[p as usize] {
+                    Some(a @ jsif::Action::Chi(_)) => Some((p, a)),
+                    _ => None,
+                })
+            });
+        match call {
+            Some((player, action)) => self.call(player, action, from, pai),
+            None => {
+                self.common.turn = (from + 1) % count;
+                self.draw();
+            }
+        }
+    }
+
+    // Abortive draws checked after a discard is accepted
+    fn check_abort(&self) -> Option<jsif::RoundEnd> {
+        let (common, internal) = (&self.common, &self.internal);
+
+        if common.player_count != 4 {
+            return None;
+        }
+        if common.reach.iter().all(|&r| r) {
+            return Some(jsif::RoundEnd::FourReach);
+        }
+        // the first discards without calls
+        if !internal.interrupted && common.rivers.iter().all(|r| r.len() == 1) {
+            let first = common.rivers[0][0];
+            let is_wind = (mjsys::OFFSET_Z..mjsys::OFFSET_Z + 4).contains(&(first as u8));
+            // no red pai for winds
+            if is_wind && common.rivers.iter().all(|r| r[0] == first) {
+                return Some(jsif::RoundEnd::FourWinds);
+            }
+        }
+        // by 2 or more players
+        if self.kan_count() == KAN_MAX && internal.kans.iter().all(|&k| (k as usize) < KAN_MAX) {
+            return Some(jsif::RoundEnd::FourKans);
+        }
+
+        None
+    }
+
+    // NAP calls the last discard
+    fn call(&mut self, player: u32, action: jsif::Action, from: u32, pai: i32) {
+        let (common, internal) = (&mut self.common, &mut self.internal);
+        let p = player as usize;
+
+        internal.interrupted = true;
+        for ippatsu in &mut internal.ippatsu {
+            *ippatsu = false;
+        }
+
+        let hand = &mut internal.hands[p];
+        let code = jsif::pai_code(pai);
+        let (mtype, mpai, remove) = match action {
+            jsif::Action::Pon => (jsif::MeldType::Pon, code, vec![code; 2]),
+            jsif::Action::BigKan => (jsif::MeldType::OpenKan, code, vec![code; 3]),
+            jsif::Action::Chi(start) => (
+                jsif::MeldType::Chi,
+                start,
+                (start..start + 3).filter(|&x| x != code).collect(),
+            ),
+            _ => panic!("Invalid call: {:?}", action),
+        };
+        let mut red = jsif::is_red(pai) as u32;
+        for x in remove {
+            // use normal pais first
+            let index = hand
+                .iter()
+                .position(|&y| y == x)
+                .or_else(|| hand.iter().position(|&y| jsif::pai_code(y) == x))
+                .unwrap();
+            red += jsif::is_red(hand.remove(index)) as u32;
+        }
+        let meld = jsif::Meld {
+            mtype,
+            pai: mpai,
+            from,
+            red,
+        };
+        common.melds[p].push(meld);
+        self.events
+            .push(jsif::GameEvent::Call { player, pai, meld });
+        common.turn = player;
+
+        if mtype == jsif::MeldType::OpenKan {
+            internal.kans[p] += 1;
+            self.draw_lingshang();
+        } else {
+            // discard without draw
+            self.update_ap_actions();
+        }
+    }
+
+    // Active player declares kan by itself
+    fn self_kan(&mut self, player: u32, action: jsif::Action) {
+        let (common, internal) = (&mut self.common, &mut self.internal);
+        let p = player as usize;
+
+        internal.interrupted = true;
+        for ippatsu in &mut internal.ippatsu {
+            *ippatsu = false;
+        }
+
+        let hand = &mut internal.hands[p];
+        if let Some(draw) = internal.draws[p].take() {
+            hand.push(draw);
+            Self::sort_hand(hand);
+        }
+        match action {
+            jsif::Action::BlindKan(pai) => {
+                let red = hand
+                    .iter()
+                    .filter(|&&x| jsif::pai_code(x) == pai && jsif::is_red(x))
+                    .count() as u32;
+                hand.retain(|&x| jsif::pai_code(x) != pai);
+                let meld = jsif::Meld {
+                    mtype: jsif::MeldType::BlindKan,
+                    pai,
+                    from: player,
+                    red,
+                };
+                common.melds[p].push(meld);
+                self.events
+                    .push(jsif::GameEvent::Call { player, pai, meld });
+            }
+            jsif::Action::SmallKan(pai) => {
+                let index = hand.iter().position(|&x| jsif::pai_code(x) == pai).unwrap();
+                let added = hand.remove(index);
+                let meld = common.melds[p]
+                    .iter_mut()
+                    .find(|m| m.mtype == jsif::MeldType::Pon && m.pai == pai)
+                    .unwrap();
+                meld.mtype = jsif::MeldType::AddedKan;
+                meld.red += jsif::is_red(added) as u32;
+                let meld = *meld;
+                self.events.push(jsif::GameEvent::Call {
+                    player,
+                    pai: added,
+                    meld,
+                });
+                internal.kans[p] += 1;
+                // others may Ron the added pai (chankan)
+                internal.last_discard = Some((player, added));
+                internal.chankan = true;
+                self.update_reactions();
+                return;
+            }
+            _ => panic!("Invalid kan: {:?}", action),
+        }
+        internal.kans[p] += 1;
+
+        self.draw_lingshang();
     }
 
     // Turn player draws a pai
@@ -246,23 +1199,1616 @@ impl GameState {
         let (common, internal) = (&mut self.common, &mut self.internal);
 
         // all player must not have draw pai
-        // yama.len() must be > 0
         for p in 0..common.player_count as usize {
             assert!(internal.draws[p].is_none());
         }
         // draw
-        internal.draws[common.turn as usize] = Some(internal.yama.pop().unwrap());
+        let pai = match internal.yama.pop() {
+            Some(pai) => pai,
+            None => {
+                self.exhaustive_draw();
+                return;
+            }
+        };
+        common.yama_rest = internal.yama.len() as u32;
+        internal.draws[common.turn as usize] = Some(pai);
+        internal.lingshang = false;
+        self.events.push(jsif::GameEvent::Draw {
+            player: common.turn,
+            pai,
+            lingshang: false,
+        });
 
-        // go to new state
+        self.update_ap_actions();
+    }
+
+    // Turn player draws a pai from wang pai after kan
+    fn draw_lingshang(&mut self) {
+        let (common, internal) = (&mut self.common, &mut self.internal);
+
+        let pai = internal.yama2.pop().unwrap();
+        // keep wang pai count by taking the last pai of yama
+        internal
+            .yama2
+            .insert(YAMA2_LINGSHANG, internal.yama.remove(0));
+        common.yama_rest = internal.yama.len() as u32;
+        internal.draws[common.turn as usize] = Some(pai);
+        internal.lingshang = true;
+        self.events.push(jsif::GameEvent::Draw {
+            player: common.turn,
+            pai,
+            lingshang: true,
+        });
+        // new dora
+        let count = common.dora.len();
+        let indicator = internal.yama2[YAMA2_DORA + count];
+        common.dora.push(indicator);
+        self.events.push(jsif::GameEvent::Dora { indicator });
+
+        self.update_ap_actions();
+    }
+
+    // Create AP action list
+    fn update_ap_actions(&mut self) {
+        let alist = self.ap_action_list(self.common.turn);
+
+        let (common, internal) = (&mut self.common, &mut self.internal);
         common.phase = jsif::GamePhase::WaitAction;
         for alist in &mut internal.actions {
             alist.clear();
         }
-        // create action list
-        let ap_actions = &mut internal.actions[common.turn as usize];
-        let ap_hand = &internal.hands[common.turn as usize];
-        for (i, _pai) in ap_hand.iter().enumerate() {
-            ap_actions.push(jsif::Action::Discard(i as i32));
+        internal.actions[common.turn as usize] = alist;
+    }
+
+    fn ap_action_list(&self, player: u32) -> Vec<jsif::Action> {
+        let (common, internal) = (&self.common, &self.internal);
+        let p = player as usize;
+        let hand = &internal.hands[p];
+        let draw = internal.draws[p];
+        let mut alist = vec![];
+
+        if common.reach[p] {
+            // the drawn pai only
+            alist.push(jsif::Action::Discard(hand.len() as i32));
+        } else {
+            for (i, _pai) in hand.iter().enumerate() {
+                alist.push(jsif::Action::Discard(i as i32));
+            }
+            if draw.is_some() {
+                alist.push(jsif::Action::Discard(hand.len() as i32));
+            }
+        }
+
+        // after Chi/Pon
+        let draw = match draw {
+            Some(draw) => draw,
+            None => return alist,
+        };
+        let mut all = hand.clone();
+        all.push(draw);
+        let codes: Vec<i32> = all.iter().map(|&x| jsif::pai_code(x)).collect();
+
+        if self.calc_win(player, draw, true).is_some() {
+            alist.push(jsif::Action::Tsumo);
+        }
+        let menzen = common.melds[p]
+            .iter()
+            .all(|m| m.mtype == jsif::MeldType::BlindKan);
+        let can_reach = !common.reach[p]
+            && menzen
+            && internal.points[p] >= REACH_DEPOSIT
+            && common.yama_rest >= common.player_count;
+        if can_reach {
+            for i in 0..all.len() {
+                let mut rest = all.clone();
+                rest.remove(i);
+                if Self::is_tenpai(&rest, &common.melds[p]) {
+                    alist.push(jsif::Action::Reach(i as i32));
+                }
+            }
+        }
+        // TODO: blind kan without changing waits while reach
+        if !common.reach[p] && common.yama_rest > 0 && self.kan_count() < KAN_MAX {
+            let mut kinds = codes.clone();
+            kinds.sort_unstable();
+            kinds.dedup();
+            for &pai in &kinds {
+                if codes.iter().filter(|&&x| x == pai).count() == 4 {
+                    alist.push(jsif::Action::BlindKan(pai));
+                }
+            }
+            for m in &common.melds[p] {
+                if m.mtype == jsif::MeldType::Pon && codes.contains(&m.pai) {
+                    alist.push(jsif::Action::SmallKan(m.pai));
+                }
+            }
+        }
+        if internal.first_turn[p] && !internal.interrupted {
+            let mut yao: Vec<i32> = codes
+                .iter()
+                .copied()
+                .filter(|&x| mjsys::is_yao(x as u8))
+                .collect();
+            yao.sort_unstable();
+            yao.dedup();
+            if yao.len() >= KYUSHU_KINDS {
+                alist.push(jsif::Action::KyushuKyuhai);
+            }
+        }
+
+        alist
+    }
+
+    fn kan_count(&self) -> usize {
+        self.internal.kans.iter().sum::<u32>() as usize
+    }
+
+    // by code, red five is the same as 5
+    fn sort_hand(hand: &mut [i32]) {
+        hand.sort_unstable_by_key(|&x| (jsif::pai_code(x), x));
+    }
+
+    fn is_tenpai(hand: &[i32], melds: &[jsif::Meld]) -> bool {
+        let mjhand = to_mjhand(hand, melds);
+
+        shanten::all(&mjhand) == 0
+    }
+
+    // all pais to finish the hand (with or without yaku)
+    fn waits(hand: &[i32], melds: &[jsif::Meld]) -> Vec<i32> {
+        let mut result = Vec::new();
+        for pai in 0..mjsys::PAI_COUNT_U8 {
+            let mut mjhand = to_mjhand(hand, melds);
+            mjhand.finish_pai = Some(pai);
+            let mut finish = Vec::new();
+            mjsys::all_finish_patterns(&mut mjhand, &mut finish).unwrap();
+            if !finish.is_empty() {
+                result.push(pai as i32);
+            }
+        }
+
+        result
+    }
+
+    fn is_furiten(&self, player: u32) -> bool {
+        let (common, internal) = (&self.common, &self.internal);
+        let p = player as usize;
+
+        if internal.furiten[p] {
+            return true;
         }
+        let waits = Self::waits(&internal.hands[p], &common.melds[p]);
+
+        common.rivers[p]
+            .iter()
+            .any(|&pai| waits.contains(&jsif::pai_code(pai)))
+    }
+
+    fn indicator_to_dora(indicator: i32) -> i32 {
+        let (kind, num) = mjsys::decode(jsif::pai_code(indicator) as u8);
+        let next = if kind == mjsys::KIND_Z {
+            // wind and sangen
+            match num {
+                4 => 1,
+                7 => 5,
+                _ => num + 1,
+            }
+        } else if num == 9 {
+            1
+        } else {
+            num + 1
+        };
+
+        mjsys::encode(kind, next) as i32
+    }
+
+    fn point_param(&self, player: u32, tumo: bool) -> mjsys::PointParam {
+        let (common, internal) = (&self.common, &self.internal);
+        let p = player as usize;
+        let count = common.player_count;
+
+        let reach = if !common.reach[p] {
+            mjsys::Reach::None
+        } else if internal.double_reach[p] {
+            mjsys::Reach::Double
+        } else {
+            mjsys::Reach::Single
+        };
+        let dora = common
+            .dora
+            .iter()
+            .map(|&x| Self::indicator_to_dora(x) as u8)
+            .collect();
+        let ura = if common.reach[p] {
+            internal.yama2[YAMA2_URA..YAMA2_URA + common.dora.len()]
+                .iter()
+                .map(|&x| Self::indicator_to_dora(x) as u8)
+                .collect()
+        } else {
+            vec![]
+        };
+
+        mjsys::PointParam {
+            field_wind: common.wind as u8,
+            self_wind: ((player + count - common.parent) % count) as u8,
+            reach,
+            reach_first: internal.ippatsu[p],
+            chankan: !tumo && internal.chankan,
+            lingshang: tumo && internal.lingshang,
+            haitei: tumo && !internal.lingshang && internal.yama.is_empty(),
+            houtei: !tumo && internal.yama.is_empty(),
+            tenchi: tumo && internal.first_turn[p] && !internal.interrupted,
+            dora,
+            ura,
+        }
+    }
+
+    // None if not finished or no yaku
+    fn calc_win(&self, player: u32, pai: i32, tumo: bool) -> Option<mjsys::Point> {
+        let (common, internal) = (&self.common, &self.internal);
+        let p = player as usize;
+
+        let melds = &common.melds[p];
+        let mut mjhand = to_mjhand(&internal.hands[p], melds);
+        mjhand.finish_pai = Some(jsif::pai_code(pai) as u8);
+        mjhand.tumo = tumo;
+        let mut finish = Vec::new();
+        mjsys::all_finish_patterns(&mut mjhand, &mut finish).unwrap();
+
+        let menzen = melds.iter().all(|m| m.mtype == jsif::MeldType::BlindKan);
+        let red = internal.hands[p]
+            .iter()
+            .chain(std::iter::once(&pai))
+            .filter(|&&x| jsif::is_red(x))
+            .count() as u32
+            + melds.iter().map(|m| m.red).sum::<u32>();
+        let param = self.point_param(player, tumo);
+        finish
+            .iter()
+            .map(|f| self.apply_rule(mjsys::calc_base_point(f, &param), menzen, red))
+            .filter(|point| point.yaku != 0 || point.yakuman != 0)
+            .max()
+    }
+
+    // rule options not in mjsys (red five, open tanyao, double yakuman)
+    fn apply_rule(&self, point: mjsys::Point, menzen: bool, red: u32) -> mjsys::Point {
+        let mut yakuman_count = point.yakuman_count;
+        let mut fan = point.fan;
+        let mut yaku = point.yaku;
+
+        if !self.rule.open_tanyao && !menzen && yaku & Yaku::TANYAO.0 != 0 {
+            yaku &= !Yaku::TANYAO.0;
+            fan -= Yaku::fan_sum(Yaku::TANYAO.0);
+        }
+        // dora only, not a yaku
+        fan += red;
+        if !self.rule.double_yakuman {
+            yakuman_count = yakuman_count.min(1);
+        }
+
+        mjsys::calc_base_point_direct(yakuman_count, fan, point.fu, yaku, point.yakuman)
+    }
+
+    // Tsumo (winners = [from]) or Ron
+    fn win(&mut self, winners: &[u32], from: u32, pai: i32, tumo: bool) {
+        let count = self.common.player_count;
+        let parent = self.common.parent;
+        let hon = self.common.hon as i32;
+
+        let mut deltas = vec![0; count as usize];
+        let mut wins = vec![];
+        for (k, &player) in winners.iter().enumerate() {
+            let point = self.calc_win(player, pai, tumo).unwrap();
+            // hon and deposit for the first winner only
+            let hon = if k == 0 { hon } else { 0 };
+
+            let mut gain = 0;
+            let mut value = 0;
+            if tumo {
+                for p in (0..count).filter(|&p| p != player) {
+                    let pay = if player == parent {
+                        point.calc_point_p_tumo()
+                    } else if p == parent {
+                        point.calc_point_c_tumo().1
+                    } else {
+                        point.calc_point_c_tumo().0
+                    } as i32;
+                    value += pay;
+                    deltas[p as usize] -= pay + hon * HON_TUMO;
+                    gain += pay + hon * HON_TUMO;
+                }
+            } else {
+                let pay = if player == parent {
+                    point.calc_point_p_ron()
+                } else {
+                    point.calc_point_c_ron()
+                } as i32;
+                value += pay;
+                deltas[from as usize] -= pay + hon * HON_RON;
+                gain += pay + hon * HON_RON;
+            }
+            if k == 0 {
+                gain += self.common.deposit as i32 * REACH_DEPOSIT;
+            }
+            deltas[player as usize] += gain;
+
+            let mut yaku_str: Vec<String> = Yakuman::to_japanese_list(point.yakuman)
+                .iter()
+                .map(|s| s.to_string())
+                .collect();
+            if point.yakuman_count == 0 {
+                yaku_str.extend(
+                    Yaku::to_japanese_list(point.yaku)
+                        .iter()
+                        .map(|s| s.to_string()),
+                );
+            }
+            wins.push(jsif::WinResult {
+                player,
+                from,
+                pai,
+                fan: point.fan,
+                fu: point.fu,
+                yakuman_count: point.yakuman_count,
+                yaku: point.yaku,
+                yakuman: point.yakuman,
+                yaku_str,
+                point: value,
+            });
+        }
+
+        let (common, internal) = (&mut self.common, &mut self.internal);
+        common.deposit = 0;
+        for (p, delta) in deltas.iter().enumerate() {
+            internal.points[p] += delta;
+        }
+        let hands = (0..count)
+            .map(|p| {
+                if winners.contains(&p) {
+                    internal.hands[p as usize].clone()
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
+        let tenpai = (0..count).map(|p| winners.contains(&p)).collect();
+
+        // parent continues with hon + 1 if parent wins
+        let renchan = winners.contains(&parent);
+        common.result = Some(jsif::RoundResult {
+            reason: if tumo {
+                jsif::RoundEnd::Tsumo
+            } else {
+                jsif::RoundEnd::Ron
+            },
+            tenpai,
+            hands,
+            deltas,
+            wins,
+            renchan,
+            hon: if renchan { common.hon + 1 } else { 0 },
+            game_over: false,
+        });
+        self.finish_round();
+    }
+
+    // Abortive draw, reveal hands of the players
+    fn abort(&mut self, reason: jsif::RoundEnd, players: &[u32]) {
+        assert!(reason.is_abort());
+        let (common, internal) = (&mut self.common, &mut self.internal);
+        let count = common.player_count;
+
+        let hands = (0..count)
+            .map(|p| {
+                if players.contains(&p) {
+                    let mut hand = internal.hands[p as usize].clone();
+                    hand.extend(internal.draws[p as usize]);
+                    hand
+                } else {
+                    vec![]
+                }
+            })
+            .collect();
+        // reach declaration is not accepted
+        if let Some(p) = internal.reach_pending.take() {
+            common.reach[p as usize] = false;
+        }
+
+        common.result = Some(jsif::RoundResult {
+            reason,
+            tenpai: vec![false; count as usize],
+            hands,
+            deltas: vec![0; count as usize],
+            wins: vec![],
+            renchan: true,
+            hon: if self.rule.abort_hon_up {
+                common.hon + 1
+            } else {
+                common.hon
+            },
+            game_over: false,
+        });
+        self.finish_round();
+    }
+
+    // Ryukyoku by empty yama
+    fn exhaustive_draw(&mut self) {
+        let (common, internal) = (&mut self.common, &mut self.internal);
+        let count = common.player_count as usize;
+
+        let tenpai: Vec<bool> = internal
+            .hands
+            .iter()
+            .zip(common.melds.iter())
+            .map(|(h, m)| Self::is_tenpai(h, m))
+            .collect();
+        let tenpai_count = tenpai.iter().filter(|&&t| t).count();
+
+        // noten bappu (nothing if all or nobody)
+        let mut deltas = vec![0; count];
+        if tenpai_count > 0 && tenpai_count < count {
+            let gain = NOTEN_PAYMENT / tenpai_count as i32;
+            let loss = NOTEN_PAYMENT / (count - tenpai_count) as i32;
+            for (p, delta) in deltas.iter_mut().enumerate() {
+                *delta = if tenpai[p] { gain } else { -loss };
+            }
+        }
+        for (p, delta) in deltas.iter().enumerate() {
+            internal.points[p] += delta;
+        }
+
+        // show tenpai hands only
+        let hands = internal
+            .hands
+            .iter()
+            .zip(tenpai.iter())
+            .map(|(h, &t)| if t { h.clone() } else { vec![] })
+            .collect();
+
+        let renchan = tenpai[common.parent as usize];
+        common.result = Some(jsif::RoundResult {
+            reason: jsif::RoundEnd::ExhaustiveDraw,
+            tenpai,
+            hands,
+            deltas,
+            wins: vec![],
+            renchan,
+            hon: common.hon + 1,
+            game_over: false,
+        });
+        self.finish_round();
+    }
+
+    fn finish_round(&mut self) {
+        let game_over = self.is_game_over();
+        let (common, internal) = (&mut self.common, &mut self.internal);
+
+        common.result.as_mut().unwrap().game_over = game_over;
+        common.phase = jsif::GamePhase::ShowResult;
+        common.yama_rest = internal.yama.len() as u32;
+        self.events.push(jsif::GameEvent::RoundEnd {
+            result: common.result.clone().unwrap(),
+            ura: internal.yama2[YAMA2_URA..YAMA2_URA + common.dora.len()].to_vec(),
+            points: internal.points.clone(),
+        });
+        for alist in &mut internal.actions {
+            alist.clear();
+            alist.push(jsif::Action::Next);
+        }
+    }
+
+    // Judge after the points are updated
+    fn is_game_over(&self) -> bool {
+        let (common, internal, rule) = (&self.common, &self.internal, &self.rule);
+        let result = common.result.as_ref().unwrap();
+
+        if rule.bust_end && internal.points.iter().any(|&x| x < 0) {
+            return true;
+        }
+
+        let top = *internal.points.iter().max().unwrap();
+        let reached = top >= rule.target_point;
+        let parent_top = internal.points[common.parent as usize] == top;
+        let all_last = common.parent == common.player_count - 1;
+        // the last round, or extended
+        let last_wind = common.wind + 1 >= common.round_max;
+        let extended = common.wind >= common.round_max;
+
+        // sudden death
+        if extended && reached {
+            return true;
+        }
+        if !last_wind || !all_last {
+            return false;
+        }
+        if result.renchan {
+            // dealer may stop if leading (or wins at extended round)
+            rule.parent_stop && parent_top && (reached || !rule.extension)
+        } else {
+            // go into the next wind if nobody reaches target point
+            let can_extend = rule.extension && !extended && common.wind + 1 < 4;
+            !can_extend || reached
+        }
+    }
+
+    // All players confirm the result and go to the next round
+    fn confirm_result(&mut self, player: u32) {
+        let internal = &mut self.internal;
+        internal.actions[player as usize].clear();
+        if !internal.actions.iter().all(|alist| alist.is_empty()) {
+            return;
+        }
+
+        let common = &self.common;
+        let result = common.result.as_ref().unwrap();
+        if result.game_over {
+            self.finish_game();
+            return;
+        }
+
+        let (wind, parent) = if result.renchan {
+            (common.wind, common.parent)
+        } else if common.parent + 1 == common.player_count {
+            (common.wind + 1, 0)
+        } else {
+            (common.wind, common.parent + 1)
+        };
+        let hon = result.hon;
+        self.next_round(wind, parent, hon);
+    }
+
+    fn finish_game(&mut self) {
+        let (common, internal, rule) = (&mut self.common, &mut self.internal, &self.rule);
+        let count = common.player_count;
+
+        // sort by point, the first parent side is upper if the same
+        let mut order: Vec<u32> = (0..count).collect();
+        order.sort_by_key(|&p| std::cmp::Reverse(internal.points[p as usize]));
+
+        if rule.deposit_to_top {
+            internal.points[order[0] as usize] += common.deposit as i32 * REACH_DEPOSIT;
+            common.deposit = 0;
+        }
+        let oka = (rule.return_point - rule.start_point) * count as i32;
+
+        let standings = order
+            .iter()
+            .enumerate()
+            .map(|(rank, &player)| {
+                let point = internal.points[player as usize];
+                let mut score = point - rule.return_point + rule.uma[rank];
+                if rank == 0 {
+                    score += oka;
+                }
+                jsif::Standing {
+                    player,
+                    rank: rank as u32,
+                    point,
+                    score,
+                }
+            })
+            .collect();
+
+        common.final_result = Some(jsif::FinalResult { standings });
+        common.phase = jsif::GamePhase::GameResult;
+        self.events.push(jsif::GameEvent::GameEnd {
+            result: common.final_result.clone().unwrap(),
+        });
+        for alist in &mut internal.actions {
+            alist.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_pai_list(src: &str) -> Vec<i32> {
+        let hand = mjsys::from_human_readable_string(src).unwrap();
+        let mut result = Vec::new();
+        for (pai, &count) in hand.bucket.iter().enumerate() {
+            for _ in 0..count {
+                result.push(pai as i32);
+            }
+        }
+
+        result
+    }
+
+    // "1m" -> pai code
+    fn to_pai(src: &str) -> i32 {
+        let num = src.as_bytes()[0] - b'0';
+        let kind = "mpsz".find(&src[1..]).unwrap() as u8;
+
+        mjsys::encode(kind, num) as i32
+    }
+
+    fn new_state() -> GameState {
+        let mut state = GameState::new();
+        state.init().unwrap();
+
+        state
+    }
+
+    fn new_state_with(rule: jsif::RuleConfig) -> GameState {
+        let mut state = GameState::new();
+        state.rule = rule;
+        state.init().unwrap();
+
+        state
+    }
+
+    // all pais in the game
+    fn all_pais(state: &GameState) -> Vec<i32> {
+        let internal = &state.internal;
+        let mut all = internal.yama.clone();
+        all.extend(&internal.yama2);
+        for p in 0..state.common.player_count as usize {
+            all.extend(&internal.hands[p]);
+            all.extend(internal.draws[p]);
+        }
+
+        all
+    }
+
+    // player 0 discards the pai and player 1 declares Ron if possible
+    fn ron_by_1(state: &mut GameState, pai: &str) -> Option<jsif::WinResult> {
+        set_draw(state, pai);
+        discard(state, pai, false);
+        if !state.internal.actions[1].contains(&jsif::Action::Ron) {
+            return None;
+        }
+        state.action(1, jsif::Action::Ron).unwrap();
+
+        Some(round_result(state).wins[0].clone())
+    }
+
+    fn set_hands(state: &mut GameState, hands: [&str; 4]) {
+        for (p, src) in hands.iter().enumerate() {
+            state.internal.hands[p] = to_pai_list(src);
+        }
+        // fixed dora (5z) not to change points
+        state.common.dora = vec![to_pai("4z")];
+    }
+
+    // replace the drawn pai of the turn player
+    fn set_draw(state: &mut GameState, pai: &str) {
+        let turn = state.common.turn as usize;
+        state.internal.draws[turn] = Some(to_pai(pai));
+        state.update_ap_actions();
+    }
+
+    // the next draws (in order)
+    fn set_yama(state: &mut GameState, pais: &[&str]) {
+        let mut yama: Vec<i32> = vec![to_pai("7z"); 30];
+        yama.extend(pais.iter().rev().map(|&x| to_pai(x)));
+        state.internal.yama = yama;
+        state.common.yama_rest = state.internal.yama.len() as u32;
+    }
+
+    fn skip_all(state: &mut GameState) {
+        while state.common.phase == jsif::GamePhase::WaitReaction {
+            let p = state
+                .internal
+                .actions
+                .iter()
+                .position(|alist| !alist.is_empty())
+                .unwrap();
+            state.action(p as u32, jsif::Action::Skip).unwrap();
+        }
+    }
+
+    // discard the pai (search from the drawn pai first)
+    fn discard(state: &mut GameState, pai: &str, reach: bool) {
+        let turn = state.common.turn;
+        let p = turn as usize;
+        let pai = to_pai(pai);
+        let hand = &state.internal.hands[p];
+        let index = if state.internal.draws[p] == Some(pai) {
+            hand.len()
+        } else {
+            hand.iter().position(|&x| x == pai).unwrap()
+        } as i32;
+        let action = if reach {
+            jsif::Action::Reach(index)
+        } else {
+            jsif::Action::Discard(index)
+        };
+        assert!(state.internal.actions[p].contains(&action), "{:?}", action);
+        state.action(turn, action).unwrap();
+    }
+
+    fn tsumogiri(state: &mut GameState) {
+        let turn = state.common.turn;
+        let index = state.internal.hands[turn as usize].len() as i32;
+        state.action(turn, jsif::Action::Discard(index)).unwrap();
+        skip_all(state);
+    }
+
+    fn round_result(state: &GameState) -> &jsif::RoundResult {
+        assert_eq!(jsif::GamePhase::ShowResult, state.common.phase);
+
+        state.common.result.as_ref().unwrap()
+    }
+
+    // empty yama and let the current turn player discard the drawn pai
+    fn force_exhaustive_draw(state: &mut GameState, hands: [&str; 4]) {
+        set_hands(state, hands);
+        set_draw(state, "7z");
+        state.internal.yama.clear();
+        state.common.yama_rest = 0;
+        tsumogiri(state);
+    }
+
+    #[test]
+    fn rule_validation() {
+        assert!(Game::new(Default::default()).is_ok());
+        assert!(Game::new(jsif::RuleConfig {
+            time_limit: 30,
+            time_extra: 60,
+            ..Default::default()
+        })
+        .is_ok());
+
+        let invalid = [
+            jsif::RuleConfig {
+                player_count: 5,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                round_max: 0,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                cpu_players: 5,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                start_point: 25050,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                return_point: 20000,
+                ..Default::default()
+            },
+            // uma for 4 players
+            jsif::RuleConfig {
+                player_count: 3,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                uma: vec![30000, 10000, -10000, -20000],
+                ..Default::default()
+            },
+            // all hands live
+            jsif::RuleConfig {
+                spectator_hands: true,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                spectator_hands: true,
+                spectator_delay: 15,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                time_limit: 601,
+                ..Default::default()
+            },
+            jsif::RuleConfig {
+                time_limit: 10,
+                time_extra: 601,
+                ..Default::default()
+            },
+            // without the limit
+            jsif::RuleConfig {
+                time_extra: 10,
+                ..Default::default()
+            },
+        ];
+        for rule in invalid {
+            assert!(Game::new(rule.clone()).is_err(), "{:?}", rule);
+        }
+    }
+
+    #[test]
+    fn three_players() {
+        // tonpuu sanma
+        let state = new_state_with(jsif::RuleConfig {
+            player_count: 3,
+            round_max: 1,
+            uma: vec![15000, 0, -15000],
+            ..Default::default()
+        });
+        assert_eq!(3, state.common.player_count);
+        assert_eq!(1, state.common.round_max);
+        assert_eq!(3, state.common.rule.player_count);
+        // 108 - 14 - 13 * 3
+        assert_eq!(55 - 1, state.common.yama_rest);
+
+        let all = all_pais(&state);
+        assert_eq!(108, all.len());
+        for pai in ["2m", "5m", "8m"] {
+            assert!(!all.contains(&to_pai(pai)));
+        }
+    }
+
+    #[test]
+    fn three_players_view() {
+        let game = Game::new(jsif::RuleConfig {
+            player_count: 3,
+            uma: vec![15000, 0, -15000],
+            ..Default::default()
+        })
+        .unwrap();
+        for player in 0..3 {
+            let local = game.get_view(player).unwrap().local;
+            // the parent (player 0) has drawn, seen from the same relative seat
+            let mut counts = [13, 13, 13, 0];
+            counts[((3 - player) % 3) as usize] = 14;
+            assert_eq!(counts, local.hand_counts, "{player}");
+            assert!(local.points[..3].iter().all(|&p| p == 25000));
+            // the 4th slot is always empty
+            assert_eq!(i32::MIN, local.points[3]);
+            assert!(local.hands[3].is_empty());
+            assert_eq!(-1, local.draws[3]);
+        }
+    }
+
+    #[test]
+    fn red_five() {
+        let state = new_state_with(jsif::RuleConfig {
+            red_five: true,
+            ..Default::default()
+        });
+        let reds: Vec<i32> = all_pais(&state)
+            .into_iter()
+            .filter(|&x| jsif::is_red(x))
+            .map(jsif::pai_code)
+            .collect();
+        assert_eq!(3, reds.len());
+        for pai in ["5m", "5p", "5s"] {
+            assert!(reds.contains(&to_pai(pai)));
+        }
+
+        let noten = "1357m2468p1357s1z";
+        let tenpai = "123456789m2355p";
+        let fan = |red: bool| {
+            let mut state = new_state();
+            set_hands(&mut state, [noten, tenpai, noten, noten]);
+            if red {
+                let hand = &mut state.internal.hands[1];
+                let i = hand.iter().position(|&x| x == to_pai("5p")).unwrap();
+                hand[i] |= jsif::PAI_RED;
+            }
+            ron_by_1(&mut state, "4p").unwrap().fan
+        };
+        assert_eq!(fan(false) + 1, fan(true));
+    }
+
+    #[test]
+    fn open_tanyao() {
+        let noten = "1357m2468p1357s1z";
+        let ron = |open_tanyao: bool| {
+            let mut state = new_state_with(jsif::RuleConfig {
+                open_tanyao,
+                ..Default::default()
+            });
+            set_hands(&mut state, [noten, noten, noten, noten]);
+            // pon 2p, tanyao only
+            state.internal.hands[1] = ["3m", "4m", "5m", "4p", "5p", "6p", "6s", "7s", "8s", "5s"]
+                .iter()
+                .map(|&x| to_pai(x))
+                .collect();
+            state.common.melds[1].push(jsif::Meld {
+                mtype: jsif::MeldType::Pon,
+                pai: to_pai("2p"),
+                from: 0,
+                red: 0,
+            });
+            ron_by_1(&mut state, "5s")
+        };
+        assert_eq!(1, ron(true).unwrap().fan);
+        assert!(ron(false).is_none());
+    }
+
+    #[test]
+    fn double_yakuman() {
+        let noten = "1357m2468p1357s1z";
+        // daisangen, tuiso
+        let tenpai = "111555666777z2z";
+        let ron = |double_yakuman: bool| {
+            let mut state = new_state_with(jsif::RuleConfig {
+                double_yakuman,
+                ..Default::default()
+            });
+            set_hands(&mut state, [noten, tenpai, noten, noten]);
+            ron_by_1(&mut state, "2z").unwrap()
+        };
+        let win = ron(true);
+        assert!(win.yakuman_count >= 2);
+        assert_eq!(32000 * win.yakuman_count as i32, win.point);
+        let win = ron(false);
+        assert_eq!(1, win.yakuman_count);
+        assert_eq!(32000, win.point);
+    }
+
+    #[test]
+    fn hidden_hands() {
+        let game = Game::new(Default::default()).unwrap();
+        let to_json = |player| serde_json::to_string(&game.get_view(player).unwrap()).unwrap();
+
+        for player in 0..4 {
+            let before = to_json(player);
+            {
+                // replace the others' concealed pais with different ones
+                let internal = &mut game.0.write().unwrap().internal;
+                for p in (0..4).filter(|&p| p != player as usize) {
+                    let change = |pai: &mut i32| *pai = (jsif::pai_code(*pai) + 1) % 34;
+                    internal.hands[p].iter_mut().for_each(change);
+                    internal.draws[p].iter_mut().for_each(change);
+                }
+            }
+            assert_eq!(before, to_json(player));
+
+            let view = game.get_view(player).unwrap();
+            assert!(view.local.hands[1..].iter().all(|h| h.is_empty()));
+            assert!(view.local.draws[1..].iter().all(|&d| d == -1));
+            assert_eq!(13, view.local.hands[0].len());
+        }
+        let view = game.get_view(0).unwrap();
+        assert_eq!([14, 13, 13, 13], view.local.hand_counts);
+        assert!(view.local.draws[0] >= 0);
+
+        // revealed at the result
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+        let mut state = new_state();
+        force_exhaustive_draw(&mut state, [tenpai, noten, noten, noten]);
+        let local = Game::convert_to_local_state(&state.common, &state.internal, Viewer::Player(1));
+        assert_eq!(to_pai_list(tenpai), local.hands[3]);
+        assert!(local.hands[1].is_empty() && local.hands[2].is_empty());
+    }
+
+    #[test]
+    fn spectator_view() {
+        let game = Game::new(Default::default()).unwrap();
+        let view = game.get_spectator_view();
+        assert!(view.local.hands.iter().all(|h| h.is_empty()));
+        assert_eq!([14, 13, 13, 13], view.local.hand_counts);
+        assert!(view.local.actions.is_empty());
+
+        // all hands, 16 discards behind
+        let rule = jsif::RuleConfig {
+            spectator_hands: true,
+            spectator_delay: 16,
+            ..Default::default()
+        };
+        let game = Game::new(rule).unwrap();
+        let tsumogiri = |game: &Game| {
+            let turn = game.0.read().unwrap().common.turn;
+            game.action(turn, jsif::Action::Discard(13)).unwrap();
+            for p in 0..4 {
+                // ignore the error if no reaction
+                let _ = game.action(p, jsif::Action::Skip);
+            }
+        };
+        // hidden until the delay passes
+        for i in 0..=16 {
+            if i > 0 {
+                tsumogiri(&game);
+            }
+            let view = game.get_spectator_view();
+            assert!(view.local.hands.iter().all(|h| h.is_empty()));
+            assert!(view.local.draws.iter().all(|&d| d == -1));
+            assert!(view.common.rivers.iter().all(|r| r.is_empty()));
+        }
+        tsumogiri(&game);
+        let view = game.get_spectator_view();
+        assert_eq!(1, view.common.rivers[0].len());
+        assert_eq!(13, view.local.hands[1].len());
+        assert!(view.local.draws[1] >= 0);
+        assert!(game.0.read().unwrap().spectator.len() <= 17);
+    }
+
+    #[test]
+    fn decision() {
+        let game = Game::new(Default::default()).unwrap();
+        // RoundStart and Draw
+        assert_eq!((2, vec![true, false, false, false]), game.decision());
+        // not changed by chat
+        game.chat(Some(1), jsif::ChatBody::Text("hi".to_string()))
+            .unwrap();
+        assert_eq!(2, game.decision().0);
+
+        let discard = jsif::Action::Discard(13);
+        assert!(game.action_since(0, 1, discard).is_err());
+        game.action_since(0, 2, discard).unwrap();
+        let (seq, pending) = game.decision();
+        assert!(seq > 3);
+        // already acted for the old decision
+        assert!(game.action_since(0, 2, discard).is_err());
+        assert!(pending.iter().any(|&p| p));
+    }
+
+    #[test]
+    fn event_log() {
+        let game = Game::new(Default::default()).unwrap();
+        game.action(0, jsif::Action::Discard(13)).unwrap();
+        for p in 1..4 {
+            // ignore the error if no reaction
+            let _ = game.action(p, jsif::Action::Skip);
+        }
+
+        let all = game.get_events(0, 0).unwrap();
+        for (i, e) in all.iter().enumerate() {
+            assert_eq!(i as u64 + 1, e.seq);
+        }
+        assert!(matches!(all[0].event, jsif::GameEvent::RoundStart { .. }));
+        assert!(matches!(
+            all[1].event,
+            jsif::GameEvent::Draw { player: 0, pai, .. } if pai >= 0
+        ));
+        assert!(matches!(
+            all[2].event,
+            jsif::GameEvent::Discard {
+                player: 0,
+                tsumogiri: true,
+                ..
+            }
+        ));
+
+        // hidden from player 1
+        let events = game.get_events(1, 0).unwrap();
+        assert_eq!(all.len(), events.len());
+        match &events[0].event {
+            jsif::GameEvent::RoundStart {
+                hands, yama, yama2, ..
+            } => {
+                assert!(yama.is_empty() && yama2.is_empty());
+                assert_eq!(13, hands[1].len());
+                assert!(hands[0].is_empty() && hands[2].is_empty() && hands[3].is_empty());
+            }
+            e => panic!("{:?}", e),
+        }
+        assert!(matches!(
+            events[1].event,
+            jsif::GameEvent::Draw {
+                player: 0,
+                pai: -1,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events[3].event,
+            jsif::GameEvent::Draw { player: 1, pai, .. } if pai >= 0
+        ));
+
+        // incremental
+        let events = game.get_events(1, 2).unwrap();
+        assert_eq!(3, events[0].seq);
+        assert!(game.get_events(1, all.len() as u64).unwrap().is_empty());
+        assert!(game.get_events(4, 0).is_err());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let game = Game::new(Default::default()).unwrap();
+        game.action(0, jsif::Action::Discard(13)).unwrap();
+
+        let loaded = Game::load(game.save()).unwrap();
+        for p in 0..4 {
+            let to_json = |game: &Game| serde_json::to_string(&game.get_view(p).unwrap()).unwrap();
+            assert_eq!(to_json(&game), to_json(&loaded));
+            assert_eq!(
+                game.get_events(p, 0).unwrap().len(),
+                loaded.get_events(p, 0).unwrap().len()
+            );
+        }
+        let (a, b) = (game.0.read().unwrap(), loaded.0.read().unwrap());
+        assert_eq!(a.internal.yama, b.internal.yama);
+        assert_eq!(a.internal.yama2, b.internal.yama2);
+
+        // corrupted saves are errors, not panics
+        let corrupt = |path: &[&str], value: serde_json::Value| {
+            let mut save = game.save();
+            let mut target = &mut save;
+            for key in path {
+                target = &mut target[*key];
+            }
+            *target = value;
+            Game::load(save)
+        };
+        assert!(corrupt(&["internal", "hands"], serde_json::json!([])).is_err());
+        assert!(corrupt(&["common", "turn"], 4.into()).is_err());
+        assert!(corrupt(&["common", "yama_rest"], 0.into()).is_err());
+        assert!(corrupt(&["internal", "points"], serde_json::json!([0, 0, 0, 0])).is_err());
+
+        // spectator hands are hidden again after the restart
+        let rule = jsif::RuleConfig {
+            spectator_hands: true,
+            spectator_delay: 16,
+            ..Default::default()
+        };
+        let game = Game::new(rule).unwrap();
+        for _ in 0..17 {
+            let turn = game.0.read().unwrap().common.turn;
+            game.action(turn, jsif::Action::Discard(13)).unwrap();
+            for p in 0..4 {
+                // ignore the error if no reaction
+                let _ = game.action(p, jsif::Action::Skip);
+            }
+        }
+        assert_eq!(13, game.get_spectator_view().local.hands[1].len());
+        let loaded = Game::load(game.save()).unwrap();
+        let view = loaded.get_spectator_view();
+        assert!(view.local.hands.iter().all(|h| h.is_empty()));
+    }
+
+    #[test]
+    fn tenhou_log() {
+        let game = Game::new(Default::default()).unwrap();
+        assert!(game.get_log("test").is_err());
+
+        {
+            let mut state = game.0.write().unwrap();
+            let noten = "1357m2468p1357s1z";
+            set_hands(&mut state, [noten, "123456789m1234p", noten, noten]);
+            ron_by_1(&mut state, "4p").unwrap();
+            state.finish_game();
+        }
+        let log = game.get_log("test").unwrap();
+        assert_eq!("test", log["title"][0]);
+        assert_eq!(8, log["sc"].as_array().unwrap().len());
+
+        let round = log["log"][0].as_array().unwrap();
+        // header, points, dora, ura, 4 * (hand, draws, discards), result
+        assert_eq!(4 + 4 * 3 + 1, round.len());
+        assert_eq!(serde_json::json!([0, 0, 0]), round[0]);
+        assert_eq!(serde_json::json!([60]), round[6]);
+        let result = round[16].as_array().unwrap();
+        assert_eq!("和了", result[0]);
+        let info = result[2].as_array().unwrap();
+        assert_eq!(serde_json::json!([1, 0, 1]), serde_json::json!(info[0..3]));
+        assert!(info.contains(&serde_json::json!("一気通貫(2飜)")));
+    }
+
+    #[test]
+    fn play_until_exhaustive_draw() {
+        let mut state = new_state();
+        // 136 - 14 - 13 * 4
+        assert_eq!(70 - 1, state.common.yama_rest);
+
+        while state.common.phase == jsif::GamePhase::WaitAction {
+            tsumogiri(&mut state);
+        }
+
+        let total: usize = state.common.rivers.iter().map(|r| r.len()).sum();
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::ExhaustiveDraw, result.reason);
+        assert_eq!(70, total);
+        assert_eq!(0, result.deltas.iter().sum::<i32>());
+    }
+
+    #[test]
+    fn noten_payment() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+
+        let mut state = new_state();
+        force_exhaustive_draw(&mut state, [tenpai, noten, noten, noten]);
+        let result = round_result(&state);
+        assert_eq!(vec![3000, -1000, -1000, -1000], result.deltas);
+        assert_eq!(vec![true, false, false, false], result.tenpai);
+        assert!(result.hands[0].len() == 13 && result.hands[1].is_empty());
+        assert!(result.renchan);
+        assert_eq!(1, result.hon);
+        assert_eq!(vec![28000, 24000, 24000, 24000], state.internal.points);
+
+        let mut state = new_state();
+        force_exhaustive_draw(&mut state, [noten, tenpai, noten, tenpai]);
+        let result = round_result(&state);
+        assert_eq!(vec![-1500, 1500, -1500, 1500], result.deltas);
+        assert!(!result.renchan);
+
+        let mut state = new_state();
+        force_exhaustive_draw(&mut state, [noten, tenpai, tenpai, tenpai]);
+        let result = round_result(&state);
+        assert_eq!(vec![-3000, 1000, 1000, 1000], result.deltas);
+
+        let mut state = new_state();
+        force_exhaustive_draw(&mut state, [noten, noten, noten, noten]);
+        let result = round_result(&state);
+        assert_eq!(vec![0, 0, 0, 0], result.deltas);
+        assert!(!result.renchan);
+    }
+
+    #[test]
+    fn tsumo_and_ron() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+
+        let mut state = new_state();
+        set_hands(&mut state, [noten, tenpai, noten, noten]);
+        state.internal.interrupted = true;
+        set_yama(&mut state, &["4p"]);
+        set_draw(&mut state, "7z");
+        tsumogiri(&mut state);
+        // ittsu, tsumo, 30 fu
+        state.action(1, jsif::Action::Tsumo).unwrap();
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::Tsumo, result.reason);
+        assert_eq!(vec![-2000, 4000, -1000, -1000], result.deltas);
+        assert_eq!(
+            (3, 30, 4000),
+            (result.wins[0].fan, result.wins[0].fu, result.wins[0].point)
+        );
+        assert!(!result.renchan);
+        assert_eq!(0, result.hon);
+
+        let mut state = new_state();
+        set_hands(&mut state, [noten, tenpai, noten, noten]);
+        state.common.hon = 2;
+        state.common.deposit = 1;
+        set_draw(&mut state, "1p");
+        discard(&mut state, "1p", false);
+        assert!(state.internal.actions[1].contains(&jsif::Action::Ron));
+        // ittsu, 40 fu
+        state.action(1, jsif::Action::Ron).unwrap();
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::Ron, result.reason);
+        assert_eq!(vec![-3200, 4200, 0, 0], result.deltas);
+        assert_eq!(0, state.common.deposit);
+    }
+
+    #[test]
+    fn furiten() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+
+        let mut state = new_state();
+        set_hands(&mut state, [noten, tenpai, noten, noten]);
+        state.common.rivers[1].push(to_pai("4p"));
+        set_draw(&mut state, "1p");
+        discard(&mut state, "1p", false);
+        assert!(!state.internal.actions[1].contains(&jsif::Action::Ron));
+    }
+
+    #[test]
+    fn kyushu_kyuhai() {
+        let noten = "1357m2468p1357s1z";
+
+        let mut state = new_state();
+        set_hands(&mut state, ["19m19p23456s1234z", noten, noten, noten]);
+        set_draw(&mut state, "5s");
+        assert!(!state.internal.actions[0].contains(&jsif::Action::KyushuKyuhai));
+        set_draw(&mut state, "9s");
+        state.action(0, jsif::Action::KyushuKyuhai).unwrap();
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::KyushuKyuhai, result.reason);
+        assert_eq!(14, result.hands[0].len());
+        assert!(result.renchan);
+        assert_eq!(1, result.hon);
+
+        // not after the first discard
+        let mut state = new_state();
+        set_hands(&mut state, ["159m19p1239s1234z", noten, noten, noten]);
+        state.internal.first_turn[0] = false;
+        set_draw(&mut state, "5z");
+        assert!(!state.internal.actions[0].contains(&jsif::Action::KyushuKyuhai));
+    }
+
+    #[test]
+    fn leftover_deposit() {
+        for deposit_to_top in [false, true] {
+            let mut state = new_state_with(jsif::RuleConfig {
+                deposit_to_top,
+                ..Default::default()
+            });
+            // a pending reach at the end
+            state.internal.points[1] -= REACH_DEPOSIT;
+            state.common.deposit += 1;
+            state.verify().unwrap();
+            state.finish_game();
+            state.verify().unwrap();
+
+            let result = state.common.final_result.as_ref().unwrap();
+            let points: i32 = result.standings.iter().map(|s| s.point).sum();
+            let scores: i32 = result.standings.iter().map(|s| s.score).sum();
+            let left = if deposit_to_top { 0 } else { REACH_DEPOSIT };
+            assert_eq!(100000 - left, points);
+            assert_eq!(-left, scores);
+        }
+    }
+
+    #[test]
+    fn force_end() {
+        let game = Game::new(Default::default()).unwrap();
+        game.force_end(false).unwrap();
+        {
+            let state = game.0.read().unwrap();
+            let result = round_result(&state);
+            assert_eq!(jsif::RoundEnd::Canceled, result.reason);
+            assert_eq!(vec![0; 4], result.deltas);
+            assert!(result.renchan);
+        }
+        game.verify().unwrap();
+        // only in a round
+        assert!(game.force_end(false).is_err());
+
+        // with a pending reach
+        let game = Game::new(Default::default()).unwrap();
+        {
+            let mut state = game.0.write().unwrap();
+            let noten = "2468m2468p2468s5z";
+            // pon is possible
+            let pon = "2468m2468p246s66z";
+            set_hands(&mut state, ["123456789m1234p", pon, noten, noten]);
+            set_draw(&mut state, "6z");
+            discard(&mut state, "6z", true);
+            assert_eq!(jsif::GamePhase::WaitReaction, state.common.phase);
+        }
+        game.force_end(true).unwrap();
+        let state = game.0.read().unwrap();
+        assert_eq!(jsif::RoundEnd::ExhaustiveDraw, round_result(&state).reason);
+        assert!(!state.common.reach[0]);
+        assert_eq!(0, state.common.deposit);
+        // noten payments
+        assert_eq!(vec![3000, -1000, -1000, -1000], round_result(&state).deltas);
+    }
+
+    #[test]
+    fn four_winds() {
+        let hand = "2468m2468p2468s1z";
+
+        let mut state = new_state();
+        set_hands(&mut state, [hand, hand, hand, hand]);
+        set_yama(&mut state, &["5z", "5z", "5z"]);
+        set_draw(&mut state, "5z");
+        for _ in 0..3 {
+            discard(&mut state, "1z", false);
+            skip_all(&mut state);
+            assert_eq!(jsif::GamePhase::WaitAction, state.common.phase);
+        }
+        discard(&mut state, "1z", false);
+        skip_all(&mut state);
+        assert_eq!(jsif::RoundEnd::FourWinds, round_result(&state).reason);
+    }
+
+    #[test]
+    fn four_reach() {
+        let hand = "123456789m1234p";
+
+        let mut state = new_state();
+        state.rule.abort_hon_up = false;
+        set_hands(&mut state, [hand, hand, hand, hand]);
+        set_yama(&mut state, &["6z", "6z", "6z"]);
+        set_draw(&mut state, "6z");
+        for _ in 0..4 {
+            discard(&mut state, "6z", true);
+            skip_all(&mut state);
+        }
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::FourReach, result.reason);
+        assert_eq!(0, result.hon);
+        assert_eq!(4, state.common.deposit);
+        assert_eq!(vec![24000; 4], state.internal.points);
+    }
+
+    #[test]
+    fn four_kans() {
+        let noten = "2468m2468p2468s5z";
+
+        let mut state = new_state();
+        set_hands(
+            &mut state,
+            ["1111z2222z13579m", "3333z4444z13579p", noten, noten],
+        );
+        set_yama(&mut state, &["7z"]);
+        // no 5th copy of the kan pais from wang pai
+        state.internal.yama2 = vec![to_pai("7z"); state.internal.yama2.len()];
+        set_draw(&mut state, "7z");
+        state
+            .action(0, jsif::Action::BlindKan(to_pai("1z")))
+            .unwrap();
+        state
+            .action(0, jsif::Action::BlindKan(to_pai("2z")))
+            .unwrap();
+        tsumogiri(&mut state);
+        assert_eq!(1, state.common.turn);
+        state
+            .action(1, jsif::Action::BlindKan(to_pai("3z")))
+            .unwrap();
+        state
+            .action(1, jsif::Action::BlindKan(to_pai("4z")))
+            .unwrap();
+        assert_eq!(5, state.common.dora.len());
+        tsumogiri(&mut state);
+        assert_eq!(jsif::RoundEnd::FourKans, round_result(&state).reason);
+    }
+
+    #[test]
+    fn chankan() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+        // player 0 has a pon of 4p and draws another
+        let pon_4p = |state: &mut GameState| {
+            let hand = &mut state.internal.hands[0];
+            hand.retain(|&x| x != to_pai("4p"));
+            hand.truncate(10);
+            state.common.melds[0].push(jsif::Meld {
+                mtype: jsif::MeldType::Pon,
+                pai: to_pai("4p"),
+                from: 2,
+                red: 0,
+            });
+            set_draw(state, "4p");
+            let kan = jsif::Action::SmallKan(to_pai("4p"));
+            assert!(state.internal.actions[0].contains(&kan));
+            state.action(0, kan).unwrap();
+        };
+
+        // ittsu and chankan, 40 fu
+        let mut state = new_state();
+        set_hands(&mut state, [noten, tenpai, noten, noten]);
+        pon_4p(&mut state);
+        assert_eq!(jsif::GamePhase::WaitReaction, state.common.phase);
+        assert_eq!(
+            vec![jsif::Action::Ron, jsif::Action::Skip],
+            state.internal.actions[1]
+        );
+        state.action(1, jsif::Action::Ron).unwrap();
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::Ron, result.reason);
+        let win = &result.wins[0];
+        assert_eq!((1, 0, 3), (win.player, win.from, win.fan));
+        assert!(win.yaku & Yaku::CHANKAN.0 != 0);
+        assert_eq!(vec![-5200, 5200, 0, 0], result.deltas);
+
+        // skipped, then the lingshang draw
+        let mut state = new_state();
+        set_hands(&mut state, [noten, tenpai, noten, noten]);
+        pon_4p(&mut state);
+        state.action(1, jsif::Action::Skip).unwrap();
+        assert_eq!(jsif::GamePhase::WaitAction, state.common.phase);
+        assert!(state.internal.lingshang && state.internal.draws[0].is_some());
+        assert_eq!(2, state.common.dora.len());
+        assert!(state.internal.furiten[1]);
+
+        // nobody can Ron
+        let mut state = new_state();
+        set_hands(&mut state, [noten, noten, noten, noten]);
+        pon_4p(&mut state);
+        assert_eq!(jsif::GamePhase::WaitAction, state.common.phase);
+        assert!(state.internal.lingshang);
+    }
+
+    #[test]
+    fn triple_ron() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+
+        let mut state = new_state();
+        set_hands(&mut state, [noten, tenpai, tenpai, tenpai]);
+        set_draw(&mut state, "1p");
+        discard(&mut state, "1p", false);
+        for p in 1..4 {
+            state.action(p, jsif::Action::Ron).unwrap();
+        }
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::TripleRon, result.reason);
+        assert_eq!(vec![0; 4], result.deltas);
+
+        // the first player takes hon and deposit
+        let mut state = new_state();
+        state.rule.abort_triple_ron = false;
+        state.common.hon = 1;
+        state.common.deposit = 1;
+        set_hands(&mut state, [noten, tenpai, tenpai, tenpai]);
+        set_draw(&mut state, "1p");
+        discard(&mut state, "1p", false);
+        for p in 1..4 {
+            state.action(p, jsif::Action::Ron).unwrap();
+        }
+        let result = round_result(&state);
+        assert_eq!(jsif::RoundEnd::Ron, result.reason);
+        assert_eq!(vec![-8100, 3900, 2600, 2600], result.deltas);
+        assert_eq!(3, result.wins.len());
+    }
+
+    fn next_all(state: &mut GameState) {
+        for p in 0..state.common.player_count {
+            state.action(p, jsif::Action::Next).unwrap();
+        }
+    }
+
+    #[test]
+    fn round_rotation() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+
+        let mut state = new_state();
+        force_exhaustive_draw(&mut state, [tenpai, noten, noten, noten]);
+        next_all(&mut state);
+        let common = &state.common;
+        assert_eq!((0, 0, 1), (common.wind, common.parent, common.hon));
+        assert_eq!(jsif::GamePhase::WaitAction, common.phase);
+
+        force_exhaustive_draw(&mut state, [noten; 4]);
+        next_all(&mut state);
+        let common = &state.common;
+        assert_eq!((0, 1, 2), (common.wind, common.parent, common.hon));
+
+        state.common.parent = 3;
+        force_exhaustive_draw(&mut state, [noten; 4]);
+        next_all(&mut state);
+        let common = &state.common;
+        assert_eq!((1, 0, 3), (common.wind, common.parent, common.hon));
+        assert_eq!(0, common.turn);
+        assert!(common.rivers.iter().all(|r| r.is_empty()));
+        assert!(state.internal.hands.iter().all(|h| h.len() == 13));
+    }
+
+    #[test]
+    fn game_end() {
+        let tenpai = "123456789m1234p";
+        let noten = "1357m2468p1357s1z";
+
+        // the last round
+        let mut state = new_state();
+        (state.common.wind, state.common.parent) = (1, 3);
+        state.internal.points = vec![35000, 25000, 20000, 20000];
+        force_exhaustive_draw(&mut state, [noten; 4]);
+        assert!(round_result(&state).game_over);
+        next_all(&mut state);
+        assert_eq!(jsif::GamePhase::GameResult, state.common.phase);
+        assert!(state.internal.actions.iter().all(|a| a.is_empty()));
+
+        // nobody reaches 30000, then sudden death
+        let mut state = new_state();
+        (state.common.wind, state.common.parent) = (1, 3);
+        force_exhaustive_draw(&mut state, [noten; 4]);
+        assert!(!round_result(&state).game_over);
+        next_all(&mut state);
+        assert_eq!((2, 0), (state.common.wind, state.common.parent));
+        state.internal.points = vec![20000, 32000, 24000, 24000];
+        force_exhaustive_draw(&mut state, [noten; 4]);
+        assert!(round_result(&state).game_over);
+
+        // bust
+        let mut state = new_state();
+        state.internal.points = vec![25000, 500, 25000, 49500];
+        force_exhaustive_draw(&mut state, [tenpai, noten, noten, noten]);
+        assert!(round_result(&state).game_over);
+
+        // parent stop
+        for parent_stop in [false, true] {
+            let mut state = new_state();
+            state.rule.parent_stop = parent_stop;
+            (state.common.wind, state.common.parent) = (1, 3);
+            state.internal.points = vec![20000, 20000, 20000, 40000];
+            force_exhaustive_draw(&mut state, [noten, noten, noten, tenpai]);
+            assert!(round_result(&state).renchan);
+            assert_eq!(parent_stop, round_result(&state).game_over);
+        }
+    }
+
+    #[test]
+    fn final_standings() {
+        let mut state = new_state();
+        state.internal.points = vec![20000, 35000, 20000, 23000];
+        state.common.deposit = 2;
+        state.finish_game();
+
+        let standings = &state.common.final_result.as_ref().unwrap().standings;
+        let players: Vec<u32> = standings.iter().map(|s| s.player).collect();
+        let points: Vec<i32> = standings.iter().map(|s| s.point).collect();
+        let scores: Vec<i32> = standings.iter().map(|s| s.score).collect();
+        // the same point: seat 0 is upper than seat 2
+        assert_eq!(vec![1, 3, 0, 2], players);
+        assert_eq!(vec![37000, 23000, 20000, 20000], points);
+        assert_eq!(vec![47000, 3000, -20000, -30000], scores);
+        assert_eq!(0, scores.iter().sum::<i32>());
+        assert_eq!(0, state.common.deposit);
     }
 }
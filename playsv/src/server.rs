@@ -1,11 +1,26 @@
-use crate::mjgame;
+use crate::account::{self, AccountStore};
+use crate::limit::RateLimiter;
+use crate::lobby::{self, Lobby};
+use crate::metrics::{self, METRICS};
+use crate::timer::TurnTimer;
+use crate::{cpu, mjai, mjgame};
 use actix_cors::Cors;
-use actix_web::{get, http, post, web, App, HttpResponse, HttpServer, Responder};
-use anyhow::Result;
+use actix_web::dev::Service;
+use actix_web::{
+    delete, get, http, middleware, post, web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use anyhow::{bail, ensure, Result};
 use game::jsif;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::BTreeMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::RwLock;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::watch;
 
 // from build.rs to this and trunk param
 const PUBLIC_URL: &str = env!("PUBLIC_URL");
@@ -27,15 +42,506 @@ fn version() -> String {
     src.join(", ")
 }
 
-// description, message, etc.
-const STRING_MAX: usize = 1024;
+// seat token length in bytes (hex string in json)
+const TOKEN_BYTES: usize = 16;
+// in the data directory
+const SNAPSHOT_FILE: &str = "rooms.json";
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+
+// without query strings (tokens and sessions)
+fn access_logger() -> middleware::Logger {
+    middleware::Logger::new("%a \"%{METHOD}xi %U\" %s %b %T")
+        .custom_request_replace("METHOD", |req| req.method().to_string())
+}
+
+// listen and serve
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // IP addresses or host names
+    pub bind: Vec<String>,
+    pub port: u16,
+    // CORS is enabled if not empty (e.g. `trunk serve`)
+    pub cors_origins: Vec<String>,
+    // actix default (physical CPU count) if None
+    pub workers: Option<usize>,
+    // save and restore rooms
+    pub data_dir: Option<PathBuf>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 8888,
+            cors_origins: vec![],
+            workers: None,
+            data_dir: None,
+        }
+    }
+}
+
+// room count and expiry
+#[derive(Debug, Clone)]
+pub struct RoomConfig {
+    pub max_rooms: usize,
+    // description, message, etc.
+    pub string_max: usize,
+    // JSON request body and WebSocket message in bytes
+    pub json_limit: usize,
+    // per client IP (unlimited if 0)
+    pub rooms_per_min: u32,
+    pub actions_per_sec: u32,
+    pub chats_per_min: u32,
+    pub logins_per_min: u32,
+    // no join, leave or action
+    pub idle_expire: Duration,
+    // after the game result
+    pub finished_expire: Duration,
+    // expiry check interval
+    pub check_interval: Duration,
+    // seated by POST /api/room/{id}/mjai
+    pub mjai_bot: Option<mjai::BotTarget>,
+    // fill the queue with CPU players after this wait (never if None)
+    pub queue_bot_fill: Option<Duration>,
+    // /api/admin/* is disabled if None
+    pub admin_token: Option<String>,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        Self {
+            max_rooms: 100,
+            string_max: 1024,
+            json_limit: 16 * 1024,
+            rooms_per_min: 5,
+            actions_per_sec: 20,
+            chats_per_min: 20,
+            logins_per_min: 10,
+            idle_expire: Duration::from_secs(60 * 60),
+            finished_expire: Duration::from_secs(10 * 60),
+            check_interval: Duration::from_secs(60),
+            mjai_bot: None,
+            queue_bot_fill: None,
+            admin_token: None,
+        }
+    }
+}
 
 // shared with all App threads
 struct AppState {
     // next game room id to be created
     next_id: AtomicU64,
-    // (id -> Game, comment) sorted list
-    rooms: RwLock<BTreeMap<u64, (mjgame::Game, String)>>,
+    // id -> room sorted list
+    rooms: RwLock<BTreeMap<u64, RoomState>>,
+    room_config: RoomConfig,
+    // by room_config
+    room_limiter: RateLimiter,
+    action_limiter: RateLimiter,
+    chat_limiter: RateLimiter,
+    // register and login
+    login_limiter: RateLimiter,
+    // matchmaking queue (lock before rooms)
+    lobby: Mutex<Lobby>,
+    // do not lock with rooms
+    accounts: Mutex<AccountStore>,
+}
+
+struct RoomState {
+    // for logging
+    id: u64,
+    game: mjgame::Game,
+    comment: String,
+    // for room deletion by the creator
+    owner_token: String,
+    // secret token for each seat (None if free)
+    seats: Vec<Option<String>>,
+    // account name for each seat (None if a guest)
+    accounts: Vec<Option<String>>,
+    // seats played by the mjai bot (restarted on restore)
+    mjai: Vec<bool>,
+    // incremented at every game state update
+    update: watch::Sender<u64>,
+    // join, leave or action (for idle expiry)
+    last_active: Mutex<Instant>,
+}
+
+// random hex string
+fn new_token() -> String {
+    // thread_local cryptographically secure PRNG
+    let mut rng = rand::thread_rng();
+
+    (0..TOKEN_BYTES)
+        .map(|_| format!("{:02x}", rng.gen::<u8>()))
+        .collect()
+}
+
+impl RoomState {
+    fn new(id: u64, game: mjgame::Game, comment: String) -> Self {
+        let rule = game.rule();
+        let count = rule.player_count as usize;
+        // the last seats for CPU players
+        let mut seats = vec![None; count];
+        for seat in &mut seats[count - rule.cpu_players as usize..] {
+            *seat = Some(new_token());
+        }
+
+        Self {
+            id,
+            game,
+            comment,
+            owner_token: new_token(),
+            seats,
+            accounts: vec![None; count],
+            mjai: vec![false; count],
+            update: watch::Sender::new(0),
+            last_active: Mutex::new(Instant::now()),
+        }
+    }
+
+    // token check, game action, and push to the watchers
+    // accepted and rejected actions are logged
+    fn action(&self, player: u32, token: &str, action: jsif::Action) -> Result<()> {
+        let result = self
+            .check_token(player, token)
+            .and_then(|_| self.game.action(player, action));
+        METRICS.action(result.is_ok());
+        if let Err(err) = result {
+            log::warn!(room = self.id, seat = player; "Rejected {action:?}: {err}");
+            return Err(err);
+        }
+        log::info!(room = self.id, seat = player; "Action {action:?}");
+        self.update.send_modify(|v| *v += 1);
+        self.touch();
+
+        Ok(())
+    }
+
+    // pass or tsumogiri for the decision by the server (logged)
+    // not activity for idle expiry
+    fn default_action(&self, player: u32, seq: u64) -> Result<()> {
+        let view = self.game.get_view(player)?;
+        ensure!(!view.local.actions.is_empty(), "No action");
+        let action = cpu::default_action(&view);
+        self.game.action_since(player, seq, action)?;
+        log::info!(room = self.id, seat = player; "Default {action:?}");
+        self.update.send_modify(|v| *v += 1);
+
+        Ok(())
+    }
+
+    // from the seat with the token, or a spectator if None
+    fn chat(&self, seat: Option<(u32, &str)>, body: jsif::ChatBody, max: usize) -> Result<()> {
+        if let jsif::ChatBody::Text(text) = &body {
+            ensure!(!text.trim().is_empty(), "Empty message");
+            ensure!(text.len() <= max, "Too long message (max: {} bytes)", max);
+        }
+        let player = match seat {
+            Some((player, token)) => {
+                self.check_token(player, token)?;
+                Some(player)
+            }
+            None => None,
+        };
+        self.game.chat(player, body.clone())?;
+        self.update.send_modify(|v| *v += 1);
+        match player {
+            Some(player) => {
+                self.touch();
+                log::info!(room = self.id, seat = player; "Chat {body:?}");
+            }
+            None => log::info!(room = self.id; "Chat {body:?}"),
+        }
+
+        Ok(())
+    }
+
+    fn touch(&self) {
+        *self.last_active.lock().unwrap() = Instant::now();
+    }
+
+    fn is_expired(&self, config: &RoomConfig, now: Instant) -> bool {
+        let idle = now.saturating_duration_since(*self.last_active.lock().unwrap());
+        let finished = self.game.phase() == jsif::GamePhase::GameResult;
+
+        idle >= config.idle_expire || (finished && idle >= config.finished_expire)
+    }
+
+    fn to_jsif(&self, id: u64) -> jsif::Room {
+        jsif::Room {
+            id,
+            comment: self.comment.clone(),
+            rule: self.game.rule(),
+            seats: self.seats.iter().map(|s| s.is_some()).collect(),
+            accounts: self.accounts.clone(),
+        }
+    }
+
+    // take the first free seat as the account if Some
+    fn join(&mut self, account: Option<String>) -> Result<jsif::SeatToken> {
+        if let Some(name) = &account {
+            ensure!(
+                !self.accounts.iter().any(|a| a.as_ref() == Some(name)),
+                "Already seated: {}",
+                name
+            );
+        }
+        let player = self.seats.iter().position(|s| s.is_none());
+        let player = match player {
+            Some(player) => player,
+            None => bail!("No free seat"),
+        };
+
+        let token = new_token();
+        self.seats[player] = Some(token.clone());
+        self.touch();
+        match &account {
+            Some(name) => log::info!(room = self.id, seat = player; "Join: {name}"),
+            None => log::info!(room = self.id, seat = player; "Join"),
+        }
+        self.accounts[player] = account;
+
+        Ok(jsif::SeatToken {
+            player: player as u32,
+            token,
+        })
+    }
+
+    fn leave(&mut self, player: u32, token: &str) -> Result<()> {
+        self.check_token(player, token)?;
+        self.seats[player as usize] = None;
+        self.accounts[player as usize] = None;
+        self.mjai[player as usize] = false;
+        self.touch();
+        log::info!(room = self.id, seat = player; "Leave");
+
+        Ok(())
+    }
+
+    // free the seat without the token (by the admin)
+    fn kick(&mut self, player: u32) -> Result<()> {
+        let seat = self.seats.get_mut(player as usize);
+        ensure!(seat.is_some(), "Invalid player: {}", player);
+        ensure!(seat.as_ref().unwrap().is_some(), "Free seat: {}", player);
+        *seat.unwrap() = None;
+        self.accounts[player as usize] = None;
+        self.mjai[player as usize] = false;
+        // seat tasks and push channels check the token at the update
+        self.update.send_modify(|v| *v += 1);
+        self.touch();
+        log::warn!(room = self.id, seat = player; "Kicked");
+
+        Ok(())
+    }
+
+    fn to_admin(&self, id: u64, now: Instant) -> jsif::AdminRoom {
+        let last = *self.last_active.lock().unwrap();
+
+        jsif::AdminRoom {
+            room: self.to_jsif(id),
+            phase: self.game.phase(),
+            idle_secs: now.saturating_duration_since(last).as_secs(),
+            updates: *self.update.borrow(),
+        }
+    }
+
+    fn check_token(&self, player: u32, token: &str) -> Result<()> {
+        let seat = self.seats.get(player as usize);
+        ensure!(seat.is_some(), "Invalid player: {}", player);
+        ensure!(
+            seat.unwrap().as_deref() == Some(token),
+            "Invalid token for player: {}",
+            player
+        );
+
+        Ok(())
+    }
+
+    // seats for CPU players
+    fn cpu_seats(&self) -> Vec<jsif::SeatToken> {
+        let count = self.seats.len() as u32;
+        let cpu = self.game.rule().cpu_players;
+        (count - cpu..count)
+            .filter_map(|player| {
+                let token = self.seats[player as usize].clone()?;
+                Some(jsif::SeatToken { player, token })
+            })
+            .collect()
+    }
+
+    fn mjai_seats(&self) -> Vec<jsif::SeatToken> {
+        (0..self.seats.len() as u32)
+            .filter(|&player| self.mjai[player as usize])
+            .filter_map(|player| {
+                let token = self.seats[player as usize].clone()?;
+                Some(jsif::SeatToken { player, token })
+            })
+            .collect()
+    }
+}
+
+// persistent data of all rooms
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    next_id: u64,
+    rooms: Vec<RoomSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoomSnapshot {
+    id: u64,
+    comment: String,
+    owner_token: String,
+    seats: Vec<Option<String>>,
+    #[serde(default)]
+    accounts: Vec<Option<String>>,
+    #[serde(default)]
+    mjai: Vec<bool>,
+    game: serde_json::Value,
+}
+
+impl AppState {
+    // no rooms
+    fn new(room_config: RoomConfig) -> Self {
+        let min = Duration::from_secs(60);
+        let sec = Duration::from_secs(1);
+
+        Self {
+            next_id: AtomicU64::new(0),
+            rooms: Default::default(),
+            room_limiter: RateLimiter::new(room_config.rooms_per_min, min),
+            action_limiter: RateLimiter::new(room_config.actions_per_sec, sec),
+            chat_limiter: RateLimiter::new(room_config.chats_per_min, min),
+            login_limiter: RateLimiter::new(room_config.logins_per_min, min),
+            lobby: Mutex::new(Lobby::new(lobby::default_presets())),
+            accounts: Default::default(),
+            room_config,
+        }
+    }
+
+    // forget idle clients
+    fn expire_limiters(&self, now: Instant) {
+        for limiter in [
+            &self.room_limiter,
+            &self.action_limiter,
+            &self.chat_limiter,
+            &self.login_limiter,
+        ] {
+            limiter.expire(now);
+        }
+    }
+
+    fn to_snapshot(&self) -> Snapshot {
+        // rlock
+        let rooms = self.rooms.read().unwrap();
+
+        Snapshot {
+            next_id: self.next_id.load(Ordering::Relaxed),
+            rooms: rooms
+                .iter()
+                .map(|(&id, room)| RoomSnapshot {
+                    id,
+                    comment: room.comment.clone(),
+                    owner_token: room.owner_token.clone(),
+                    seats: room.seats.clone(),
+                    accounts: room.accounts.clone(),
+                    mjai: room.mjai.clone(),
+                    game: room.game.save(),
+                })
+                .collect(),
+        }
+    }
+
+    fn from_snapshot(snapshot: Snapshot, room_config: RoomConfig) -> Result<Self> {
+        let mut rooms = BTreeMap::new();
+        for room in snapshot.rooms {
+            // a broken room should not stop the others
+            let game = match mjgame::Game::load(room.game) {
+                Ok(game) => game,
+                Err(err) => {
+                    log::error!(room = room.id; "Skipped the room: {err:#}");
+                    continue;
+                }
+            };
+            let mut state = RoomState::new(room.id, game, room.comment);
+            state.owner_token = room.owner_token;
+            state.seats = room.seats;
+            // empty in old snapshots
+            if room.accounts.len() == state.seats.len() {
+                state.accounts = room.accounts;
+            }
+            if room.mjai.len() == state.seats.len() {
+                state.mjai = room.mjai;
+            }
+            rooms.insert(room.id, state);
+        }
+
+        Ok(Self {
+            next_id: AtomicU64::new(snapshot.next_id),
+            rooms: RwLock::new(rooms),
+            ..Self::new(room_config)
+        })
+    }
+
+    // write to a temporary file and rename
+    fn save(&self, dir: &Path) -> Result<()> {
+        let json = serde_json::to_string(&self.to_snapshot())?;
+        let path = dir.join(SNAPSHOT_FILE);
+        let tmp = path.with_extension("tmp");
+        fs::create_dir_all(dir)?;
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+
+    // empty if no snapshot
+    fn load(dir: Option<&Path>, room_config: RoomConfig) -> Result<Self> {
+        let path = dir.map(|dir| dir.join(SNAPSHOT_FILE));
+        let mut state = match path {
+            Some(path) if path.exists() => {
+                let snapshot = serde_json::from_str(&fs::read_to_string(&path)?)?;
+                let state = Self::from_snapshot(snapshot, room_config)?;
+                log::info!(
+                    "Restored {} rooms from {}",
+                    state.rooms.read().unwrap().len(),
+                    path.display()
+                );
+                state
+            }
+            _ => Self::new(room_config),
+        };
+        state.accounts = Mutex::new(AccountStore::load(dir)?);
+
+        Ok(state)
+    }
+}
+
+// remove finished or idle rooms
+fn expire_rooms(rooms: &mut BTreeMap<u64, RoomState>, config: &RoomConfig, now: Instant) {
+    rooms.retain(|id, room| {
+        let expired = room.is_expired(config, now);
+        if expired {
+            log::info!(room = id; "Room expired");
+        }
+        !expired
+    });
+}
+
+// ?token=xxx for seat access
+#[derive(Deserialize)]
+struct TokenQuery {
+    #[serde(default)]
+    token: String,
+}
+
+// ?token=xxx&since=N
+#[derive(Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    token: String,
+    // the last seq number received
+    #[serde(default)]
+    since: u64,
 }
 
 /// /info
@@ -51,6 +557,78 @@ async fn info() -> impl Responder {
     HttpResponse::Ok().json(info)
 }
 
+// peer address (not X-Forwarded-For, which can be forged)
+fn client_ip(req: &HttpRequest) -> IpAddr {
+    req.peer_addr()
+        .map(|addr| addr.ip())
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+fn too_many_requests() -> HttpResponse {
+    HttpResponse::TooManyRequests().json(jsif::ErrorMsg::new("Too many requests".to_string()))
+}
+
+// extractor errors as jsif::ErrorMsg
+fn extractor_config(json_limit: usize) -> impl FnOnce(&mut web::ServiceConfig) {
+    use actix_web::error::{InternalError, JsonPayloadError};
+
+    move |cfg| {
+        let json = web::JsonConfig::default()
+            .limit(json_limit)
+            .error_handler(move |err, _req| {
+                let resp = match &err {
+                    JsonPayloadError::Overflow { .. }
+                    | JsonPayloadError::OverflowKnownLength { .. } => {
+                        let msg = format!("Too large body (max: {json_limit} bytes)");
+                        HttpResponse::PayloadTooLarge().json(jsif::ErrorMsg::new(msg))
+                    }
+                    _ => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+                };
+                InternalError::from_response(err, resp).into()
+            });
+        let query = web::QueryConfig::default().error_handler(|err, _req| {
+            let resp = HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()));
+            InternalError::from_response(err, resp).into()
+        });
+        let path = web::PathConfig::default().error_handler(|err, _req| {
+            let resp = HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()));
+            InternalError::from_response(err, resp).into()
+        });
+        cfg.app_data(json).app_data(query).app_data(path);
+    }
+}
+
+// curl localhost:8888/api/metrics
+#[get("/api/metrics")]
+async fn get_metrics(data: web::Data<AppState>) -> impl Responder {
+    let gauges = {
+        // rlock
+        let rooms = data.rooms.read().unwrap();
+        let phases = [
+            jsif::GamePhase::WaitAction,
+            jsif::GamePhase::WaitReaction,
+            jsif::GamePhase::ShowResult,
+            jsif::GamePhase::GameResult,
+        ];
+        metrics::Gauges {
+            rooms: rooms.len(),
+            phases: phases
+                .iter()
+                .map(|&phase| {
+                    let count = rooms.values().filter(|r| r.game.phase() == phase).count();
+                    (format!("{phase:?}"), count)
+                })
+                .collect(),
+            wasm_bytes: WASM.len(),
+        }
+        // unlock
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(METRICS.render(&gauges))
+}
+
 #[get("/")]
 async fn root() -> impl Responder {
     file_serve("index.html").await
@@ -62,8 +640,6 @@ async fn index(path: web::Path<String>) -> impl Responder {
 }
 
 async fn file_serve(name: &str) -> impl Responder {
-    println!("GET /{name}");
-
     match name {
         "index.html" => HttpResponse::Ok().content_type("text/html").body(INDEX),
         "client.js" => HttpResponse::Ok().content_type("text/javascript").body(JS),
@@ -81,13 +657,7 @@ async fn get_rooms(data: web::Data<AppState>) -> impl Responder {
     {
         // rlock
         let rooms = data.rooms.read().unwrap();
-        let list = rooms
-            .iter()
-            .map(|(id, (_game, comment))| jsif::Room {
-                id: *id,
-                comment: comment.clone(),
-            })
-            .collect();
+        let list = rooms.iter().map(|(&id, room)| room.to_jsif(id)).collect();
         result = jsif::RoomList(list);
         // unlock
     }
@@ -96,36 +666,51 @@ async fn get_rooms(data: web::Data<AppState>) -> impl Responder {
 }
 
 // curl -X POST -H "Content-Type: application/json" -d '{"comment": "aaa"}' -v localhost:8888/room
+// curl -X POST -H "Content-Type: application/json" -d '{"comment": "aaa", "rule": {"round_max": 1}}' -v localhost:8888/room
 #[post("/api/room")]
 async fn post_room(
     data: web::Data<AppState>,
     param: web::Json<jsif::CreateRoom>,
+    req: HttpRequest,
 ) -> impl Responder {
-    println!("POST /api/rooms {:?}", param);
-    if param.comment.len() > STRING_MAX {
-        return HttpResponse::BadRequest().finish();
+    if !data.room_limiter.check(client_ip(&req), Instant::now()) {
+        return too_many_requests();
+    }
+    let max = data.room_config.string_max;
+    if param.comment.len() > max {
+        let msg = format!("Too long comment (max: {max} bytes)");
+        return HttpResponse::BadRequest().json(jsif::ErrorMsg::new(msg));
     }
 
     // create a new game state
-    let new_game = match mjgame::Game::new() {
+    let new_game = match mjgame::Game::new(param.rule.clone()) {
         Ok(game) => game,
         Err(err) => {
             return HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()));
         }
     };
 
-    let (id, comment) = {
+    let result = {
         // wlock
         let mut rooms = data.rooms.write().unwrap();
+        if rooms.len() >= data.room_config.max_rooms {
+            let msg = format!("Too many rooms (max: {})", data.room_config.max_rooms);
+            return HttpResponse::ServiceUnavailable().json(jsif::ErrorMsg::new(msg));
+        }
         // load next id and increment atomically
         let id = data.next_id.fetch_add(1, Ordering::Relaxed);
-        rooms.insert(id, (new_game, param.comment.clone()));
+        let room = RoomState::new(id, new_game, param.comment.clone());
+        log::info!(room = id; "Room created: {:?}", param.comment);
+        let result = jsif::CreateRoomResult {
+            room: room.to_jsif(id),
+            owner_token: room.owner_token.clone(),
+        };
+        spawn_tasks(&data, id, &room);
+        rooms.insert(id, room);
 
-        (id, param.comment.clone())
+        result
         // unlock
     };
-
-    let result = jsif::Room { id, comment };
     let body = serde_json::to_string(&result).unwrap();
 
     HttpResponse::Ok()
@@ -133,20 +718,70 @@ async fn post_room(
         .body(body)
 }
 
-#[get("/api/room/{id}/{player}")]
-async fn get_room_id_player(
+// curl -X DELETE localhost:8888/api/room/0?token=xxx
+#[delete("/api/room/{id}")]
+async fn delete_room_id(
     data: web::Data<AppState>,
-    path: web::Path<(u64, u32)>,
+    path: web::Path<u64>,
+    query: web::Query<TokenQuery>,
 ) -> impl Responder {
-    let (id, player) = path.into_inner();
+    let id = path.into_inner();
 
     {
-        // rlock game list
-        let games = data.rooms.read().unwrap();
-        let game = games.get(&id);
-        if let Some(game) = game {
-            let view = game.0.get_view(player);
-            match view {
+        // wlock
+        let mut rooms = data.rooms.write().unwrap();
+        match rooms.get(&id) {
+            Some(room) if room.owner_token == query.token => {
+                // push channels are closed by dropping the sender
+                rooms.remove(&id);
+                log::info!(room = id; "Room deleted");
+                HttpResponse::Ok().finish()
+            }
+            Some(_) => HttpResponse::Forbidden().json(jsif::ErrorMsg::new(
+                "Invalid token for the owner".to_string(),
+            )),
+            None => HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string())),
+        }
+        // unlock
+    }
+}
+
+// ?session=xxx to play as the account
+#[derive(Deserialize)]
+struct SessionQuery {
+    #[serde(default)]
+    session: Option<String>,
+}
+
+// account name for the session if Some
+fn session_account(data: &AppState, session: Option<&str>) -> Result<Option<String>> {
+    match session {
+        Some(session) => {
+            let accounts = data.accounts.lock().unwrap();
+            Ok(Some(accounts.session_name(session, Instant::now())?))
+        }
+        None => Ok(None),
+    }
+}
+
+// curl -X POST localhost:8888/api/room/0/join?session=xxx
+#[post("/api/room/{id}/join")]
+async fn post_room_id_join(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    query: web::Query<SessionQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    let account = match session_account(&data, query.session.as_deref()) {
+        Ok(account) => account,
+        Err(err) => return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string())),
+    };
+
+    {
+        // wlock game list to update seats
+        let mut rooms = data.rooms.write().unwrap();
+        if let Some(room) = rooms.get_mut(&id) {
+            match room.join(account) {
                 Ok(result) => HttpResponse::Ok().json(result),
                 Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
             }
@@ -157,49 +792,2226 @@ async fn get_room_id_player(
     }
 }
 
-pub async fn server_main(port: u16, cors_enable: bool) -> Result<()> {
-    // create shared state object (Arc internally)
-    let app_state = web::Data::new(AppState {
-        next_id: AtomicU64::new(0),
-        rooms: Default::default(),
-    });
+// curl -X POST localhost:8888/api/room/0/mjai?token=xxx
+#[post("/api/room/{id}/mjai")]
+async fn post_room_id_mjai(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
 
-    println!("{}", version());
-    println!("WASM: {} KiB", WASM.len() / 1024);
-    println!("http://127.0.0.1:{port}{PUBLIC_URL}/");
-    println!("CORS: {}", if cors_enable { "Enabled" } else { "Disabled" });
+    let target = match &data.room_config.mjai_bot {
+        Some(target) => target.clone(),
+        None => {
+            return HttpResponse::BadRequest()
+                .json(jsif::ErrorMsg::new("No mjai bot configured".to_string()))
+        }
+    };
+    {
+        // rlock game list
+        let rooms = data.rooms.read().unwrap();
+        match rooms.get(&id) {
+            Some(room) if room.owner_token == query.token => {}
+            Some(_) => {
+                return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(
+                    "Invalid token for the owner".to_string(),
+                ))
+            }
+            None => {
+                return HttpResponse::BadRequest()
+                    .json(jsif::ErrorMsg::new("Invalid id".to_string()))
+            }
+        }
+        // unlock
+    }
 
-    // pass a function as App builder
-    // move app_state into closure
-    HttpServer::new(move || {
-        // for `trunk serve`
-        let cors = if cors_enable {
-            Cors::default()
-                .allowed_origin("http://127.0.0.1:8080")
-                .allowed_methods(vec!["GET", "POST"])
-                .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
-                .allowed_header(http::header::CONTENT_TYPE)
-                .supports_credentials()
-                .max_age(3600)
-        } else {
-            Cors::default()
+    // start the bot before taking a seat
+    let bot = match web::block(move || target.start()).await {
+        Ok(Ok(bot)) => bot,
+        Ok(Err(err)) => {
+            return HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()))
+        }
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(jsif::ErrorMsg::new(err.to_string()))
+        }
+    };
+
+    let seat = {
+        // wlock game list to update seats
+        let mut rooms = data.rooms.write().unwrap();
+        let result = match rooms.get_mut(&id) {
+            Some(room) => room.join(None).inspect(|seat| {
+                room.mjai[seat.player as usize] = true;
+            }),
+            None => Err(anyhow::anyhow!("Invalid id")),
         };
+        match result {
+            Ok(seat) => seat,
+            Err(err) => {
+                return HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()))
+            }
+        }
+        // unlock
+    };
 
-        App::new().wrap(cors).app_data(app_state.clone()).service(
-            web::scope(PUBLIC_URL)
-                .service(root)
-                .service(index)
-                .service(info)
-                .service(get_rooms)
-                .service(post_room)
-                .service(get_room_id_player),
-        )
-    })
-    .bind(("127.0.0.1", port))?
-    .run()
-    .await?;
+    let player = mjai::MjaiPlayer::new(bot, id, seat.player);
+    actix_web::rt::spawn(run_mjai(data.clone(), id, seat.clone(), player));
 
-    Ok(())
+    HttpResponse::Ok().json(seat)
+}
+
+// background tasks for a new or restored room
+fn spawn_tasks(data: &web::Data<AppState>, id: u64, room: &RoomState) {
+    for seat in room.cpu_seats() {
+        actix_web::rt::spawn(run_cpu(data.clone(), id, seat));
+    }
+    for seat in room.mjai_seats() {
+        actix_web::rt::spawn(restart_mjai(data.clone(), id, seat));
+    }
+    actix_web::rt::spawn(run_record(data.clone(), id));
+    if room.game.rule().time_limit > 0 {
+        actix_web::rt::spawn(run_timer(data.clone(), id));
+    }
+}
+
+// act for the CPU player at each update until the game ends
+async fn run_cpu(data: web::Data<AppState>, id: u64, seat: jsif::SeatToken) {
+    let mut update = match data.rooms.read().unwrap().get(&id) {
+        Some(room) => room.update.subscribe(),
+        None => return,
+    };
+    // the current state at first
+    update.mark_changed();
+    // until the room is deleted
+    while update.changed().await.is_ok() {
+        let rooms = data.rooms.read().unwrap();
+        let room = match rooms.get(&id) {
+            Some(room) => room,
+            None => break,
+        };
+        let view = match room.game.get_view(seat.player) {
+            Ok(view) => view,
+            Err(_) => break,
+        };
+        // kicked by the admin
+        if view.common.phase == jsif::GamePhase::GameResult
+            || room.check_token(seat.player, &seat.token).is_err()
+        {
+            break;
+        }
+        if let Some(action) = cpu::decide(&view, seat.player) {
+            // logged in action()
+            let _ = room.action(seat.player, &seat.token, action);
+        }
+    }
+}
+
+// default actions for the players out of time until the game ends
+async fn run_timer(data: web::Data<AppState>, id: u64) {
+    let (mut update, mut timer) = match data.rooms.read().unwrap().get(&id) {
+        Some(room) => match TurnTimer::new(&room.game.rule()) {
+            Some(timer) => (room.update.subscribe(), timer),
+            None => return,
+        },
+        None => return,
+    };
+    loop {
+        let deadline = {
+            let rooms = data.rooms.read().unwrap();
+            let room = match rooms.get(&id) {
+                Some(room) => room,
+                None => break,
+            };
+            if room.game.phase() == jsif::GamePhase::GameResult {
+                break;
+            }
+            let now = Instant::now();
+            let (seq, pending) = room.game.decision();
+            timer.update(seq, &pending, now);
+            for player in timer.expired(now) {
+                log::info!(room = id, seat = player; "Time out");
+                if let Err(err) = room.default_action(player, seq) {
+                    log::warn!(room = id, seat = player; "Time out error: {err:#}");
+                }
+            }
+            // the next decision if timed out
+            let (seq, pending) = room.game.decision();
+            timer.update(seq, &pending, now);
+            timer.deadline()
+            // unlock
+        };
+
+        let sleep = async {
+            match deadline {
+                Some(deadline) => actix_web::rt::time::sleep_until(deadline.into()).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            changed = update.changed() => {
+                // room deleted
+                if changed.is_err() {
+                    break;
+                }
+            }
+            _ = sleep => {}
+        }
+    }
+}
+
+// update the ratings and stats of the accounts when the game ends
+async fn run_record(data: web::Data<AppState>, id: u64) {
+    let mut update = match data.rooms.read().unwrap().get(&id) {
+        Some(room) => room.update.subscribe(),
+        None => return,
+    };
+    // the current state at first
+    update.mark_changed();
+    // until the room is deleted
+    while update.changed().await.is_ok() {
+        let (ranks, events) = {
+            let rooms = data.rooms.read().unwrap();
+            let room = match rooms.get(&id) {
+                Some(room) => room,
+                None => break,
+            };
+            let final_result = match room.game.get_view(0) {
+                Ok(view) => view.common.final_result,
+                Err(_) => break,
+            };
+            let Some(final_result) = final_result else {
+                continue;
+            };
+            // by seat
+            let mut ranks: Vec<(Option<String>, u32)> = vec![(None, 0); room.accounts.len()];
+            for s in final_result.standings {
+                ranks[s.player as usize] = (room.accounts[s.player as usize].clone(), s.rank);
+            }
+            let events = room.game.get_events(0, 0).unwrap_or_default();
+            (ranks, events)
+            // unlock
+        };
+
+        // rated only with accounts in all seats
+        if ranks.iter().all(|(name, _)| name.is_some()) {
+            let time = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let result = data
+                .accounts
+                .lock()
+                .unwrap()
+                .record_game(id, &ranks, &events, time);
+            if let Err(err) = result {
+                log::error!(room = id; "Record error: {err:#}");
+            }
+        }
+        break;
+    }
+}
+
+// start the bot again for the seat in a restored room, or free the seat
+async fn restart_mjai(data: web::Data<AppState>, id: u64, seat: jsif::SeatToken) {
+    let result = match data.room_config.mjai_bot.clone() {
+        Some(target) => match web::block(move || target.start()).await {
+            Ok(result) => result,
+            Err(err) => Err(anyhow::anyhow!("{err}")),
+        },
+        None => Err(anyhow::anyhow!("No mjai bot configured")),
+    };
+    match result {
+        Ok(bot) => {
+            let player = mjai::MjaiPlayer::new(bot, id, seat.player);
+            run_mjai(data, id, seat, player).await;
+        }
+        Err(err) => {
+            log::error!(room = id, seat = seat.player; "mjai bot restart error: {err:#}");
+            if let Some(room) = data.rooms.write().unwrap().get_mut(&id) {
+                // may be left already
+                let _ = room.leave(seat.player, &seat.token);
+            }
+        }
+    }
+}
+
+// send events to the bot and do its actions until the game ends
+async fn run_mjai(
+    data: web::Data<AppState>,
+    id: u64,
+    seat: jsif::SeatToken,
+    mut player: mjai::MjaiPlayer,
+) {
+    let mut update = match data.rooms.read().unwrap().get(&id) {
+        Some(room) => room.update.subscribe(),
+        None => return,
+    };
+    // the current state at first
+    update.mark_changed();
+    // until the room is deleted
+    while update.changed().await.is_ok() {
+        let input = {
+            let rooms = data.rooms.read().unwrap();
+            match rooms.get(&id) {
+                Some(room) => room.check_token(seat.player, &seat.token).and_then(|_| {
+                    let (seq, _) = room.game.decision();
+                    let events = room.game.get_events(seat.player, player.since())?;
+                    let view = room.game.get_view(seat.player)?;
+                    Ok((seq, events, view))
+                }),
+                None => break,
+            }
+        };
+        // left the seat
+        let Ok((seq, events, view)) = input else {
+            break;
+        };
+        let finished = view.common.phase == jsif::GamePhase::GameResult;
+
+        // the bot may be slow, without the lock
+        let result = web::block(move || {
+            let result = player.step(&events, &view);
+            (player, result)
+        })
+        .await;
+        let action = match result {
+            Ok((p, Ok(action))) => {
+                player = p;
+                action
+            }
+            Ok((_, Err(err))) => {
+                log::error!(room = id, seat = seat.player; "mjai bot error: {err:#}");
+                // the built-in CPU for the rest (the bot again on restore)
+                actix_web::rt::spawn(run_cpu(data.clone(), id, seat));
+                break;
+            }
+            Err(err) => {
+                log::error!(room = id, seat = seat.player; "mjai task error: {err}");
+                actix_web::rt::spawn(run_cpu(data.clone(), id, seat));
+                break;
+            }
+        };
+        if let Some(action) = action {
+            let rooms = data.rooms.read().unwrap();
+            if let Some(room) = rooms.get(&id) {
+                let result = room.action(seat.player, &seat.token, action);
+                // not to stall the game (fails if outdated, then the next update)
+                if result.is_err() && room.check_token(seat.player, &seat.token).is_ok() {
+                    let _ = room.default_action(seat.player, seq);
+                }
+            }
+        }
+        if finished {
+            break;
+        }
+    }
+}
+
+// create rooms for the waiting players
+fn matchmake(data: &web::Data<AppState>, now: Instant) {
+    let mut lobby = data.lobby.lock().unwrap();
+    lobby.expire(now);
+    for table in lobby.form_tables(now, data.room_config.queue_bot_fill) {
+        let game = match mjgame::Game::new(table.rule) {
+            Ok(game) => game,
+            Err(err) => {
+                log::error!("Invalid preset {}: {err:#}", table.preset);
+                continue;
+            }
+        };
+
+        // wlock
+        let mut rooms = data.rooms.write().unwrap();
+        if rooms.len() >= data.room_config.max_rooms {
+            // retry later
+            log::warn!("Too many rooms for the queue");
+            break;
+        }
+        let id = data.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut room = RoomState::new(id, game, format!("Queue: {}", table.preset));
+        for (ticket, account) in table.tickets.iter().zip(table.accounts) {
+            // free seats are more than tickets, an account waits once
+            let seat = room.join(account).unwrap();
+            lobby.set_matched(ticket, id, seat);
+        }
+        log::info!(room = id; "Room created by the queue: {}", table.preset);
+        spawn_tasks(data, id, &room);
+        rooms.insert(id, room);
+        // unlock
+    }
+}
+
+// curl localhost:8888/api/queue
+#[get("/api/queue")]
+async fn get_queue(data: web::Data<AppState>) -> impl Responder {
+    let result = data.lobby.lock().unwrap().to_jsif();
+
+    HttpResponse::Ok().json(result)
+}
+
+// curl -X POST -H "Content-Type: application/json" -d '{"preset": "tonpuu"}' localhost:8888/api/queue
+#[post("/api/queue")]
+async fn post_queue(
+    data: web::Data<AppState>,
+    param: web::Json<jsif::EnterQueue>,
+    req: HttpRequest,
+) -> impl Responder {
+    // may create a room
+    if !data.room_limiter.check(client_ip(&req), Instant::now()) {
+        return too_many_requests();
+    }
+
+    let account = match session_account(&data, param.session.as_deref()) {
+        Ok(account) => account,
+        Err(err) => return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string())),
+    };
+
+    let ticket = new_token();
+    let result =
+        data.lobby
+            .lock()
+            .unwrap()
+            .enter(&param.preset, ticket.clone(), account, Instant::now());
+    if let Err(err) = result {
+        return HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+    log::info!("Queue: {}", param.preset);
+    matchmake(&data, Instant::now());
+
+    HttpResponse::Ok().json(jsif::QueueTicket {
+        ticket,
+        preset: param.preset.clone(),
+    })
+}
+
+// curl localhost:8888/api/queue/xxx
+#[get("/api/queue/{ticket}")]
+async fn get_queue_ticket(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let now = Instant::now();
+    matchmake(&data, now);
+
+    let result = data.lobby.lock().unwrap().status(&path, now);
+    match result {
+        Ok(status) => HttpResponse::Ok().json(status),
+        Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl -X DELETE localhost:8888/api/queue/xxx
+#[delete("/api/queue/{ticket}")]
+async fn delete_queue_ticket(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let result = data.lobby.lock().unwrap().cancel(&path);
+    match result {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl -X POST -H "Content-Type: application/json" -d '{"name": "aaa", "password": "xxxxxxxx"}' localhost:8888/api/account
+#[post("/api/account")]
+async fn post_account(
+    data: web::Data<AppState>,
+    param: web::Json<jsif::Credentials>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !data.login_limiter.check(client_ip(&req), Instant::now()) {
+        return too_many_requests();
+    }
+    if let Err(err) = account::validate(&param) {
+        return HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+
+    // hash without the lock
+    let password = param.password.clone();
+    let hash = match web::block(move || account::hash_password(&password)).await {
+        Ok(hash) => hash,
+        Err(err) => {
+            return HttpResponse::InternalServerError().json(jsif::ErrorMsg::new(err.to_string()))
+        }
+    };
+    let result = data.accounts.lock().unwrap().register(&param.name, hash);
+    match result {
+        Ok(account_info) => {
+            log::info!("Account registered: {}", param.name);
+            HttpResponse::Ok().json(account_info)
+        }
+        Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl -X POST -H "Content-Type: application/json" -d '{"name": "aaa", "password": "xxxxxxxx"}' localhost:8888/api/account/login
+#[post("/api/account/login")]
+async fn post_account_login(
+    data: web::Data<AppState>,
+    param: web::Json<jsif::Credentials>,
+    req: HttpRequest,
+) -> impl Responder {
+    if !data.login_limiter.check(client_ip(&req), Instant::now()) {
+        return too_many_requests();
+    }
+
+    let hash = data.accounts.lock().unwrap().password_hash(&param.name);
+    let password = param.password.clone();
+    let verified =
+        web::block(move || hash.is_some_and(|hash| account::verify_password(&password, &hash)))
+            .await;
+    match verified {
+        Ok(true) => {
+            let session = data
+                .accounts
+                .lock()
+                .unwrap()
+                .login(&param.name, Instant::now());
+            log::info!("Login: {}", param.name);
+            HttpResponse::Ok().json(session)
+        }
+        Ok(false) => {
+            log::warn!("Login failed: {}", param.name);
+            HttpResponse::Forbidden()
+                .json(jsif::ErrorMsg::new("Invalid name or password".to_string()))
+        }
+        Err(err) => HttpResponse::InternalServerError().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl -X DELETE localhost:8888/api/account/login?session=xxx
+#[delete("/api/account/login")]
+async fn delete_account_login(
+    data: web::Data<AppState>,
+    query: web::Query<SessionQuery>,
+) -> impl Responder {
+    let Some(session) = &query.session else {
+        return HttpResponse::BadRequest().json(jsif::ErrorMsg::new("No session".to_string()));
+    };
+    match data.accounts.lock().unwrap().logout(session) {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// ranking
+// curl localhost:8888/api/account
+#[get("/api/account")]
+async fn get_accounts(data: web::Data<AppState>) -> impl Responder {
+    let result = data.accounts.lock().unwrap().ranking();
+
+    HttpResponse::Ok().json(result)
+}
+
+// curl localhost:8888/api/account/aaa
+#[get("/api/account/{name}")]
+async fn get_account_name(data: web::Data<AppState>, path: web::Path<String>) -> impl Responder {
+    let result = data.accounts.lock().unwrap().info(&path);
+    match result {
+        Ok(account_info) => HttpResponse::Ok().json(account_info),
+        Err(err) => HttpResponse::NotFound().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl localhost:8888/api/account/aaa/history
+#[get("/api/account/{name}/history")]
+async fn get_account_name_history(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let result = data.accounts.lock().unwrap().history(&path);
+    match result {
+        Ok(history) => HttpResponse::Ok().json(history),
+        Err(err) => HttpResponse::NotFound().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl localhost:8888/api/player/aaa/stats
+#[get("/api/player/{name}/stats")]
+async fn get_player_name_stats(
+    data: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let result = data.accounts.lock().unwrap().stats(&path);
+    match result {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(err) => HttpResponse::NotFound().json(jsif::ErrorMsg::new(err.to_string())),
+    }
+}
+
+// curl -X POST localhost:8888/api/room/0/0/leave?token=xxx
+#[post("/api/room/{id}/{player}/leave")]
+async fn post_room_id_player_leave(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    let (id, player) = path.into_inner();
+
+    {
+        // wlock game list to update seats
+        let mut rooms = data.rooms.write().unwrap();
+        if let Some(room) = rooms.get_mut(&id) {
+            match room.leave(player, &query.token) {
+                Ok(()) => HttpResponse::Ok().finish(),
+                Err(err) => HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string())),
+            }
+        } else {
+            HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string()))
+        }
+        // unlock
+    }
+}
+
+// ?since=N
+#[derive(Deserialize)]
+struct ChatQuery {
+    // the last seq number received
+    #[serde(default)]
+    since: u64,
+}
+
+// chat events (public), also pushed to the seats over WebSocket
+// curl localhost:8888/api/room/0/chat?since=0
+#[get("/api/room/{id}/chat")]
+async fn get_room_id_chat(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    query: web::Query<ChatQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    {
+        // rlock game list (each game has its own lock)
+        let rooms = data.rooms.read().unwrap();
+        match rooms.get(&id) {
+            Some(room) => HttpResponse::Ok().json(jsif::EventList(room.game.get_chat(query.since))),
+            None => HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string())),
+        }
+        // unlock
+    }
+}
+
+// from a spectator
+// curl -X POST -H "Content-Type: application/json" -d '{"Stamp": "Nice"}' localhost:8888/api/room/0/chat
+#[post("/api/room/{id}/chat")]
+async fn post_room_id_chat(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    param: web::Json<jsif::ChatBody>,
+    req: HttpRequest,
+) -> impl Responder {
+    let id = path.into_inner();
+
+    chat_response(&data, id, None, param.into_inner(), &req)
+}
+
+// from the seat
+// curl -X POST -H "Content-Type: application/json" -d '{"Text": "hi"}' localhost:8888/api/room/0/0/chat?token=xxx
+#[post("/api/room/{id}/{player}/chat")]
+async fn post_room_id_player_chat(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<TokenQuery>,
+    param: web::Json<jsif::ChatBody>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (id, player) = path.into_inner();
+
+    chat_response(
+        &data,
+        id,
+        Some((player, &query.token)),
+        param.into_inner(),
+        &req,
+    )
+}
+
+fn chat_response(
+    data: &AppState,
+    id: u64,
+    seat: Option<(u32, &str)>,
+    body: jsif::ChatBody,
+    req: &HttpRequest,
+) -> HttpResponse {
+    if !data.chat_limiter.check(client_ip(req), Instant::now()) {
+        return too_many_requests();
+    }
+
+    {
+        // rlock game list (each game has its own lock)
+        let rooms = data.rooms.read().unwrap();
+        let room = match rooms.get(&id) {
+            Some(room) => room,
+            None => {
+                return HttpResponse::BadRequest()
+                    .json(jsif::ErrorMsg::new("Invalid id".to_string()))
+            }
+        };
+        if let Some((player, token)) = seat {
+            if let Err(err) = room.check_token(player, token) {
+                return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+            }
+        }
+        match room.chat(seat, body, data.room_config.string_max) {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+        }
+        // unlock
+    }
+}
+
+// curl localhost:8888/api/room/0/spectate
+#[get("/api/room/{id}/spectate")]
+async fn get_room_id_spectate(data: web::Data<AppState>, path: web::Path<u64>) -> impl Responder {
+    let id = path.into_inner();
+
+    {
+        // rlock game list
+        let rooms = data.rooms.read().unwrap();
+        if let Some(room) = rooms.get(&id) {
+            HttpResponse::Ok().json(room.game.get_spectator_view())
+        } else {
+            HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string()))
+        }
+        // unlock
+    }
+}
+
+// curl localhost:8888/api/room/0/log
+#[get("/api/room/{id}/log")]
+async fn get_room_id_log(data: web::Data<AppState>, path: web::Path<u64>) -> impl Responder {
+    let id = path.into_inner();
+
+    {
+        // rlock game list
+        let rooms = data.rooms.read().unwrap();
+        if let Some(room) = rooms.get(&id) {
+            match room.game.get_log(&room.comment) {
+                Ok(log) => HttpResponse::Ok().json(log),
+                Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+            }
+        } else {
+            HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string()))
+        }
+        // unlock
+    }
+}
+
+// curl "localhost:8888/api/room/0/0/events?token=xxx&since=0"
+#[get("/api/room/{id}/{player}/events")]
+async fn get_room_id_player_events(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<EventsQuery>,
+) -> impl Responder {
+    let (id, player) = path.into_inner();
+
+    {
+        // rlock game list
+        let rooms = data.rooms.read().unwrap();
+        if let Some(room) = rooms.get(&id) {
+            if let Err(err) = room.check_token(player, &query.token) {
+                return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+            }
+            match room.game.get_events(player, query.since) {
+                Ok(events) => HttpResponse::Ok().json(jsif::EventList(events)),
+                Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+            }
+        } else {
+            HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string()))
+        }
+        // unlock
+    }
+}
+
+// curl localhost:8888/api/room/0/0?token=xxx
+#[get("/api/room/{id}/{player}")]
+async fn get_room_id_player(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    let (id, player) = path.into_inner();
+
+    {
+        // rlock game list
+        let games = data.rooms.read().unwrap();
+        let game = games.get(&id);
+        if let Some(room) = game {
+            if let Err(err) = room.check_token(player, &query.token) {
+                return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+            }
+            let view = room.game.get_view(player);
+            match view {
+                Ok(result) => HttpResponse::Ok().json(result),
+                Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+            }
+        } else {
+            HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string()))
+        }
+        // unlock
+    }
+}
+
+// curl -X POST -H "Content-Type: application/json" -d '{"Discard": 0}' localhost:8888/api/room/0/0?token=xxx
+#[post("/api/room/{id}/{player}")]
+async fn post_room_id_player(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<TokenQuery>,
+    param: web::Json<jsif::Action>,
+    req: HttpRequest,
+) -> impl Responder {
+    let (id, player) = path.into_inner();
+    if !data.action_limiter.check(client_ip(&req), Instant::now()) {
+        return too_many_requests();
+    }
+
+    {
+        // rlock game list (each game has its own lock)
+        let games = data.rooms.read().unwrap();
+        let game = games.get(&id);
+        if let Some(room) = game {
+            let action = param.into_inner();
+            if let Err(err) = room.check_token(player, &query.token) {
+                METRICS.action(false);
+                log::warn!(room = id, seat = player; "Rejected {action:?}: {err}");
+                return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+            }
+            match room.action(player, &query.token, action) {
+                Ok(()) => HttpResponse::Ok().finish(),
+                Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+            }
+        } else {
+            HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string()))
+        }
+        // unlock
+    }
+}
+
+// new LocalView for each update, and Action from the client
+// websocat ws://localhost:8888/api/room/0/0/ws?token=xxx
+#[get("/api/room/{id}/{player}/ws")]
+async fn get_room_id_player_ws(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<TokenQuery>,
+    req: HttpRequest,
+    body: web::Payload,
+) -> actix_web::Result<HttpResponse> {
+    let (id, player) = path.into_inner();
+    let token = query.into_inner().token;
+
+    let mut update = {
+        // rlock game list
+        let rooms = data.rooms.read().unwrap();
+        let room = match rooms.get(&id) {
+            Some(room) => room,
+            None => {
+                let msg = jsif::ErrorMsg::new("Invalid id".to_string());
+                return Ok(HttpResponse::BadRequest().json(msg));
+            }
+        };
+        if let Err(err) = room.check_token(player, &token) {
+            return Ok(HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string())));
+        }
+        room.update.subscribe()
+        // unlock
+    };
+
+    let ip = client_ip(&req);
+    let (response, mut session, stream) = actix_ws::handle(&req, body)?;
+    let mut stream = stream.max_frame_size(data.room_config.json_limit);
+    actix_web::rt::spawn(async move {
+        // the current view (and all chat) at first
+        update.mark_changed();
+        let mut chat_since = 0;
+        'session: loop {
+            let msgs = tokio::select! {
+                changed = update.changed() => {
+                    if changed.is_err() {
+                        // room deleted
+                        break;
+                    }
+                    let result = {
+                        let rooms = data.rooms.read().unwrap();
+                        match rooms.get(&id) {
+                            Some(room) => room
+                                .check_token(player, &token)
+                                .and_then(|_| room.game.get_view(player))
+                                .map(|view| (view, room.game.get_chat(chat_since))),
+                            None => break,
+                        }
+                    };
+                    // left the seat
+                    let Ok((view, chat)) = result else {
+                        break;
+                    };
+                    let mut msgs = vec![jsif::PushMessage::View(Box::new(view))];
+                    for e in chat {
+                        chat_since = e.seq;
+                        msgs.push(jsif::PushMessage::Chat(e));
+                    }
+                    msgs
+                }
+                msg = stream.recv() => match msg {
+                    Some(Ok(actix_ws::Message::Text(text))) => {
+                        let result = if data.action_limiter.check(ip, Instant::now()) {
+                            serde_json::from_str::<jsif::Action>(&text).map_err(anyhow::Error::from)
+                        } else {
+                            Err(anyhow::anyhow!("Too many requests"))
+                        };
+                        let result = result.and_then(|action| {
+                            let rooms = data.rooms.read().unwrap();
+                            match rooms.get(&id) {
+                                Some(room) => room.action(player, &token, action),
+                                None => Err(anyhow::anyhow!("Invalid id")),
+                            }
+                        });
+                        match result {
+                            // the view will be pushed by the update
+                            Ok(()) => continue,
+                            Err(err) => vec![jsif::PushMessage::Error(jsif::ErrorMsg::new(err.to_string()))],
+                        }
+                    }
+                    Some(Ok(actix_ws::Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    Some(Ok(actix_ws::Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            };
+            for msg in msgs {
+                let text = serde_json::to_string(&msg).unwrap();
+                if session.text(text).await.is_err() {
+                    break 'session;
+                }
+            }
+        }
+        let _ = session.close(None).await;
+    });
+
+    Ok(response)
+}
+
+// ?token=xxx for /api/admin/*
+fn check_admin(config: &RoomConfig, token: &str) -> Result<()> {
+    match &config.admin_token {
+        Some(admin) => ensure!(admin == token, "Invalid admin token"),
+        None => bail!("Admin API is disabled"),
+    }
+
+    Ok(())
+}
+
+// curl localhost:8888/api/admin/room?token=xxx
+#[get("/api/admin/room")]
+async fn get_admin_rooms(
+    data: web::Data<AppState>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    if let Err(err) = check_admin(&data.room_config, &query.token) {
+        return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+
+    let now = Instant::now();
+    let result: Vec<jsif::AdminRoom> = {
+        // rlock
+        let rooms = data.rooms.read().unwrap();
+        rooms
+            .iter()
+            .map(|(&id, room)| room.to_admin(id, now))
+            .collect()
+        // unlock
+    };
+
+    HttpResponse::Ok().json(result)
+}
+
+// full GameState with the wall and all hands
+// curl localhost:8888/api/admin/room/0?token=xxx
+#[get("/api/admin/room/{id}")]
+async fn get_admin_room_id(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(err) = check_admin(&data.room_config, &query.token) {
+        return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+
+    {
+        // rlock
+        let rooms = data.rooms.read().unwrap();
+        match rooms.get(&id) {
+            Some(room) => HttpResponse::Ok().json(room.game.save()),
+            None => HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string())),
+        }
+        // unlock
+    }
+}
+
+// ?token=xxx&draw=true
+#[derive(Deserialize)]
+struct EndQuery {
+    #[serde(default)]
+    token: String,
+    // exhaustive draw if true, abort if false
+    #[serde(default)]
+    draw: bool,
+}
+
+// end the current round
+// curl -X POST "localhost:8888/api/admin/room/0/end?token=xxx&draw=true"
+#[post("/api/admin/room/{id}/end")]
+async fn post_admin_room_id_end(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    query: web::Query<EndQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(err) = check_admin(&data.room_config, &query.token) {
+        return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+
+    {
+        // rlock game list (each game has its own lock)
+        let rooms = data.rooms.read().unwrap();
+        let result = match rooms.get(&id) {
+            Some(room) => room.game.force_end(query.draw).map(|_| {
+                room.update.send_modify(|v| *v += 1);
+                room.touch();
+            }),
+            None => Err(anyhow::anyhow!("Invalid id")),
+        };
+        match result {
+            Ok(()) => {
+                log::warn!(room = id, draw = query.draw; "Round ended by admin");
+                HttpResponse::Ok().finish()
+            }
+            Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+        }
+        // unlock
+    }
+}
+
+// curl -X POST localhost:8888/api/admin/room/0/1/kick?token=xxx
+#[post("/api/admin/room/{id}/{player}/kick")]
+async fn post_admin_room_id_player_kick(
+    data: web::Data<AppState>,
+    path: web::Path<(u64, u32)>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    let (id, player) = path.into_inner();
+    if let Err(err) = check_admin(&data.room_config, &query.token) {
+        return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+
+    {
+        // wlock game list to update seats
+        let mut rooms = data.rooms.write().unwrap();
+        let result = match rooms.get_mut(&id) {
+            Some(room) => room.kick(player),
+            None => Err(anyhow::anyhow!("Invalid id")),
+        };
+        match result {
+            Ok(()) => HttpResponse::Ok().finish(),
+            Err(err) => HttpResponse::BadRequest().json(jsif::ErrorMsg::new(err.to_string())),
+        }
+        // unlock
+    }
+}
+
+// curl -X DELETE localhost:8888/api/admin/room/0?token=xxx
+#[delete("/api/admin/room/{id}")]
+async fn delete_admin_room_id(
+    data: web::Data<AppState>,
+    path: web::Path<u64>,
+    query: web::Query<TokenQuery>,
+) -> impl Responder {
+    let id = path.into_inner();
+    if let Err(err) = check_admin(&data.room_config, &query.token) {
+        return HttpResponse::Forbidden().json(jsif::ErrorMsg::new(err.to_string()));
+    }
+
+    {
+        // wlock
+        let mut rooms = data.rooms.write().unwrap();
+        // push channels are closed by dropping the sender
+        match rooms.remove(&id) {
+            Some(_) => {
+                log::warn!(room = id; "Room deleted by admin");
+                HttpResponse::Ok().finish()
+            }
+            None => HttpResponse::BadRequest().json(jsif::ErrorMsg::new("Invalid id".to_string())),
+        }
+        // unlock
+    }
+}
+
+pub async fn server_main(config: ServerConfig, room_config: RoomConfig) -> Result<()> {
+    let data_dir = config.data_dir.clone();
+    // create shared state object (Arc internally)
+    let app_state = web::Data::new(AppState::load(data_dir.as_deref(), room_config)?);
+
+    // save rooms periodically
+    if let Some(dir) = data_dir.clone() {
+        let app_state = app_state.clone();
+        actix_web::rt::spawn(async move {
+            let mut interval = actix_web::rt::time::interval(SNAPSHOT_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(err) = app_state.save(&dir) {
+                    log::error!("Snapshot error: {err:#}");
+                }
+            }
+        });
+    }
+
+    // CPU players, mjai bots, rating and timers in restored rooms
+    for (&id, room) in app_state.rooms.read().unwrap().iter() {
+        spawn_tasks(&app_state, id, room);
+    }
+
+    // expire rooms in background
+    {
+        let app_state = app_state.clone();
+        actix_web::rt::spawn(async move {
+            let config = &app_state.room_config;
+            let mut interval = actix_web::rt::time::interval(config.check_interval);
+            loop {
+                interval.tick().await;
+                {
+                    let mut rooms = app_state.rooms.write().unwrap();
+                    expire_rooms(&mut rooms, config, Instant::now());
+                }
+                app_state.expire_limiters(Instant::now());
+                // bot fill without polling
+                matchmake(&app_state, Instant::now());
+            }
+        });
+    }
+
+    log::info!("{}", version());
+    log::info!("WASM: {} KiB", WASM.len() / 1024);
+    for addr in &config.bind {
+        // [::1]:8888
+        let host = if addr.contains(':') {
+            format!("[{addr}]")
+        } else {
+            addr.clone()
+        };
+        log::info!("http://{host}:{}{PUBLIC_URL}/", config.port);
+    }
+    if config.cors_origins.is_empty() {
+        log::info!("CORS: Disabled");
+    } else {
+        log::info!("CORS: {}", config.cors_origins.join(", "));
+    }
+    if let Some(dir) = &data_dir {
+        log::info!("Data: {}", dir.display());
+    }
+
+    // pass a function as App builder
+    // move app_state into closure
+    let shutdown_state = app_state.clone();
+    let origins = config.cors_origins.clone();
+    let json_limit = app_state.room_config.json_limit;
+    let mut server = HttpServer::new(move || {
+        // for `trunk serve`
+        let cors = if !origins.is_empty() {
+            origins
+                .iter()
+                .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+                .allowed_methods(vec!["GET", "POST", "DELETE"])
+                .allowed_headers(vec![http::header::AUTHORIZATION, http::header::ACCEPT])
+                .allowed_header(http::header::CONTENT_TYPE)
+                .supports_credentials()
+                .max_age(3600)
+        } else {
+            Cors::default()
+        };
+
+        // request log
+        App::new()
+            .wrap(access_logger())
+            // status and latency for /api/metrics
+            .wrap_fn(|req, srv| {
+                let start = Instant::now();
+                let fut = srv.call(req);
+                async move {
+                    let res = fut.await;
+                    let status = match &res {
+                        Ok(res) => res.status(),
+                        Err(err) => err.as_response_error().status_code(),
+                    };
+                    METRICS.response(status.as_u16(), start.elapsed());
+                    res
+                }
+            })
+            .wrap(cors)
+            .app_data(app_state.clone())
+            .configure(extractor_config(json_limit))
+            .service(
+                web::scope(PUBLIC_URL)
+                    .service(root)
+                    .service(index)
+                    .service(info)
+                    .service(get_metrics)
+                    .service(get_rooms)
+                    .service(post_room)
+                    .service(delete_room_id)
+                    .service(get_queue)
+                    .service(post_queue)
+                    .service(get_queue_ticket)
+                    .service(delete_queue_ticket)
+                    .service(post_account)
+                    .service(post_account_login)
+                    .service(delete_account_login)
+                    .service(get_accounts)
+                    .service(get_account_name)
+                    .service(get_account_name_history)
+                    .service(get_player_name_stats)
+                    // before /api/room/{id}/{player}
+                    .service(post_room_id_join)
+                    .service(post_room_id_mjai)
+                    .service(post_room_id_player_leave)
+                    .service(get_room_id_spectate)
+                    .service(get_room_id_log)
+                    .service(get_room_id_chat)
+                    .service(post_room_id_chat)
+                    .service(post_room_id_player_chat)
+                    .service(get_room_id_player_ws)
+                    .service(get_room_id_player_events)
+                    .service(get_room_id_player)
+                    .service(post_room_id_player)
+                    .service(get_admin_rooms)
+                    .service(get_admin_room_id)
+                    .service(post_admin_room_id_end)
+                    .service(post_admin_room_id_player_kick)
+                    .service(delete_admin_room_id),
+            )
+    });
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    for addr in &config.bind {
+        server = server.bind((addr.as_str(), config.port))?;
+    }
+    server.run().await?;
+
+    // after graceful shutdown
+    if let Some(dir) = &data_dir {
+        shutdown_state.save(dir)?;
+        log::info!("Saved to {}", dir.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{call_and_read_body_json, call_service, init_service, TestRequest};
+
+    fn new_room() -> RoomState {
+        let game = mjgame::Game::new(Default::default()).unwrap();
+
+        RoomState::new(0, game, "test".to_string())
+    }
+
+    #[test]
+    fn seat_token() {
+        let mut room = new_room();
+        let seats: Vec<jsif::SeatToken> = (0..4).map(|_| room.join(None).unwrap()).collect();
+        assert!(room.join(None).is_err());
+        assert_eq!(vec![true; 4], room.to_jsif(0).seats);
+
+        for (p, seat) in seats.iter().enumerate() {
+            assert_eq!(p as u32, seat.player);
+            assert!(room.check_token(seat.player, &seat.token).is_ok());
+            // other seat's token and no token
+            assert!(room
+                .check_token(seat.player, &seats[(p + 1) % 4].token)
+                .is_err());
+            assert!(room.check_token(seat.player, "").is_err());
+        }
+        assert!(room.check_token(4, &seats[0].token).is_err());
+
+        assert!(room.leave(1, &seats[0].token).is_err());
+        room.leave(1, &seats[1].token).unwrap();
+        assert_eq!(vec![true, false, true, true], room.to_jsif(0).seats);
+        assert!(room.check_token(1, &seats[1].token).is_err());
+        // the free seat is taken again with a new token
+        let seat = room.join(None).unwrap();
+        assert_eq!(1, seat.player);
+        assert_ne!(seats[1].token, seat.token);
+    }
+
+    #[test]
+    fn action_update() {
+        let mut room = new_room();
+        let seat = room.join(None).unwrap();
+        let mut update = room.update.subscribe();
+        assert!(!update.has_changed().unwrap());
+
+        // rejected action does not update
+        assert!(room.action(0, "", jsif::Action::Discard(0)).is_err());
+        assert!(room.action(0, &seat.token, jsif::Action::Next).is_err());
+        assert!(!update.has_changed().unwrap());
+
+        room.action(0, &seat.token, jsif::Action::Discard(0))
+            .unwrap();
+        assert!(update.has_changed().unwrap());
+        assert_eq!(1, *update.borrow_and_update());
+    }
+
+    #[test]
+    fn room_expiry() {
+        let config = RoomConfig::default();
+        let mut rooms = BTreeMap::new();
+        rooms.insert(0, new_room());
+        rooms.insert(1, new_room());
+        let now = Instant::now();
+
+        expire_rooms(&mut rooms, &config, now + Duration::from_secs(1));
+        assert_eq!(2, rooms.len());
+
+        // room 1 is active
+        let later = now + config.idle_expire;
+        *rooms[&1].last_active.lock().unwrap() = later;
+        expire_rooms(&mut rooms, &config, later);
+        assert_eq!(vec![1], rooms.keys().copied().collect::<Vec<_>>());
+
+        // a finished game expires earlier (played by CPU decisions)
+        let rule = jsif::RuleConfig {
+            round_max: 1,
+            ..Default::default()
+        };
+        let game = mjgame::Game::new(rule).unwrap();
+        while game.phase() != jsif::GamePhase::GameResult {
+            for p in 0..4 {
+                if let Some(action) = cpu::decide(&game.get_view(p).unwrap(), p) {
+                    game.action(p, action).unwrap();
+                }
+            }
+        }
+        rooms.insert(2, RoomState::new(2, game, "test".to_string()));
+        *rooms[&2].last_active.lock().unwrap() = later;
+        let finished = later + config.finished_expire;
+        expire_rooms(&mut rooms, &config, finished - Duration::from_secs(1));
+        assert_eq!(vec![1, 2], rooms.keys().copied().collect::<Vec<_>>());
+        expire_rooms(&mut rooms, &config, finished);
+        assert_eq!(vec![1], rooms.keys().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn snapshot() {
+        let dir = std::env::temp_dir().join(format!("playsv-test-{}", new_token()));
+        let state = AppState::load(Some(&dir), Default::default()).unwrap();
+        assert!(state.rooms.read().unwrap().is_empty());
+
+        state.next_id.store(3, Ordering::Relaxed);
+        let mut room = new_room();
+        let seat = room.join(None).unwrap();
+        room.action(0, &seat.token, jsif::Action::Discard(0))
+            .unwrap();
+        room.mjai[0] = true;
+        state.rooms.write().unwrap().insert(2, room);
+        state.save(&dir).unwrap();
+
+        let loaded = AppState::load(Some(&dir), Default::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(3, loaded.next_id.load(Ordering::Relaxed));
+        let rooms = loaded.rooms.read().unwrap();
+        let room = &rooms[&2];
+        assert!(room.check_token(0, &seat.token).is_ok());
+        assert_eq!(vec![true, false, false, false], room.to_jsif(2).seats);
+        assert_eq!(vec![true, false, false, false], room.mjai);
+        let view = room.game.get_view(0).unwrap();
+        assert_eq!(1, view.common.rivers[0].len());
+        drop(rooms);
+
+        // a corrupted room is skipped
+        let mut snapshot = loaded.to_snapshot();
+        let mut bad = serde_json::to_value(&snapshot.rooms[0]).unwrap();
+        bad["id"] = 5.into();
+        bad["game"]["internal"]["hands"] = serde_json::json!([]);
+        snapshot.rooms.push(serde_json::from_value(bad).unwrap());
+        let restored = AppState::from_snapshot(snapshot, Default::default()).unwrap();
+        let rooms = restored.rooms.read().unwrap();
+        assert_eq!(vec![2], rooms.keys().copied().collect::<Vec<_>>());
+    }
+
+    #[actix_web::test]
+    async fn room_limit_and_delete() {
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            max_rooms: 2,
+            ..Default::default()
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_room)
+                .service(delete_room_id),
+        )
+        .await;
+        let create = || {
+            TestRequest::post()
+                .uri("/api/room")
+                .set_json(jsif::CreateRoom {
+                    comment: "test".to_string(),
+                    rule: Default::default(),
+                })
+                .to_request()
+        };
+
+        let first: jsif::CreateRoomResult = call_and_read_body_json(&app, create()).await;
+        let resp = call_service(&app, create()).await;
+        assert!(resp.status().is_success());
+        let resp = call_service(&app, create()).await;
+        assert_eq!(http::StatusCode::SERVICE_UNAVAILABLE, resp.status());
+
+        let id = first.room.id;
+        for token in ["", "xxx"] {
+            let uri = format!("/api/room/{id}?token={token}");
+            let resp = call_service(&app, TestRequest::delete().uri(&uri).to_request()).await;
+            assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+        }
+        let uri = format!("/api/room/{id}?token={}", first.owner_token);
+        let resp = call_service(&app, TestRequest::delete().uri(&uri).to_request()).await;
+        assert!(resp.status().is_success());
+        assert!(!app_state.rooms.read().unwrap().contains_key(&id));
+
+        // room is available again
+        let resp = call_service(&app, create()).await;
+        assert!(resp.status().is_success());
+    }
+
+    // log records of the access logger
+    struct LogCapture(Mutex<Vec<String>>);
+
+    impl log::Log for LogCapture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            if record.target() == "actix_web::middleware::logger" {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[actix_web::test]
+    async fn access_log() {
+        static CAPTURE: LogCapture = LogCapture(Mutex::new(vec![]));
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .wrap(access_logger())
+                .service(delete_room_id),
+        )
+        .await;
+        let req = TestRequest::delete()
+            .uri("/api/room/1?token=secret")
+            .to_request();
+        actix_web::test::call_and_read_body(&app, req).await;
+
+        let lines = CAPTURE.0.lock().unwrap();
+        assert!(lines
+            .iter()
+            .any(|line| line.contains("\"DELETE /api/room/1\"")));
+        assert!(lines.iter().all(|line| !line.contains("secret")));
+    }
+
+    #[actix_web::test]
+    async fn request_limits() {
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            string_max: 16,
+            json_limit: 1024,
+            rooms_per_min: 2,
+            actions_per_sec: 2,
+            ..Default::default()
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .configure(extractor_config(1024))
+                .service(post_room)
+                .service(post_room_id_join)
+                .service(post_room_id_player),
+        )
+        .await;
+        let addr: std::net::SocketAddr = "192.168.0.1:10000".parse().unwrap();
+        let create = |comment: &str| {
+            TestRequest::post()
+                .uri("/api/room")
+                .peer_addr(addr)
+                .set_json(jsif::CreateRoom {
+                    comment: comment.to_string(),
+                    rule: Default::default(),
+                })
+                .to_request()
+        };
+        let error = |resp: actix_web::dev::ServiceResponse| async {
+            let status = resp.status();
+            let body = actix_web::test::read_body(resp).await;
+            let msg: jsif::ErrorMsg = serde_json::from_slice(&body).unwrap();
+            (status, msg)
+        };
+
+        // body size before parsing the comment
+        let (status, _) = error(call_service(&app, create(&"x".repeat(2000))).await).await;
+        assert_eq!(http::StatusCode::PAYLOAD_TOO_LARGE, status);
+        // comment length
+        let (status, _) = error(call_service(&app, create(&"x".repeat(17))).await).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, status);
+        // invalid json
+        let req = TestRequest::post()
+            .uri("/api/room")
+            .insert_header(http::header::ContentType::json())
+            .set_payload("{")
+            .to_request();
+        let (status, _) = error(call_service(&app, req).await).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, status);
+
+        // 2 per min, including the too long comment
+        let resp = call_service(&app, create("test")).await;
+        assert!(resp.status().is_success());
+        let (status, _) = error(call_service(&app, create("test")).await).await;
+        assert_eq!(http::StatusCode::TOO_MANY_REQUESTS, status);
+        // from another address
+        let req = TestRequest::post()
+            .uri("/api/room")
+            .peer_addr("192.168.0.2:10000".parse().unwrap())
+            .set_json(jsif::CreateRoom {
+                comment: "test".to_string(),
+                rule: Default::default(),
+            })
+            .to_request();
+        let room: jsif::CreateRoomResult = call_and_read_body_json(&app, req).await;
+
+        // 2 actions per sec
+        let uri = format!("/api/room/{}/join", room.room.id);
+        let seat: jsif::SeatToken =
+            call_and_read_body_json(&app, TestRequest::post().uri(&uri).to_request()).await;
+        let uri = format!("/api/room/{}/0?token={}", room.room.id, seat.token);
+        let action = || {
+            TestRequest::post()
+                .uri(&uri)
+                .peer_addr(addr)
+                .set_json(jsif::Action::Next)
+                .to_request()
+        };
+        for _ in 0..2 {
+            let (status, _) = error(call_service(&app, action()).await).await;
+            assert_eq!(http::StatusCode::BAD_REQUEST, status);
+        }
+        let (status, _) = error(call_service(&app, action()).await).await;
+        assert_eq!(http::StatusCode::TOO_MANY_REQUESTS, status);
+
+        // invalid path
+        let req = TestRequest::post()
+            .uri("/api/room/x/0")
+            .set_json(jsif::Action::Next)
+            .to_request();
+        let (status, _) = error(call_service(&app, req).await).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, status);
+    }
+
+    #[actix_web::test]
+    async fn websocket_limits() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            json_limit: 1024,
+            actions_per_sec: 2,
+            ..Default::default()
+        }));
+        let mut room = new_room();
+        let seat = room.join(None).unwrap();
+        app_state.rooms.write().unwrap().insert(0, room);
+
+        // a real server for the upgrade
+        let data = app_state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(data.clone())
+                .service(get_room_id_player_ws)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let url = format!("ws://{addr}/api/room/0/0/ws?token={}", seat.token);
+        let connect = || tokio_tungstenite::connect_async(url.clone());
+        let error_message = |msg: Message| match serde_json::from_str(msg.to_text().unwrap()) {
+            Ok(jsif::PushMessage::Error(err)) => Some(err.message),
+            _ => None,
+        };
+
+        // 2 actions per sec, then rejected
+        let (mut ws, _) = connect().await.unwrap();
+        let action = serde_json::to_string(&jsif::Action::Next).unwrap();
+        for _ in 0..3 {
+            ws.send(Message::text(action.clone())).await.unwrap();
+        }
+        let mut errors = vec![];
+        while errors.len() < 3 {
+            let msg = ws.next().await.unwrap().unwrap();
+            errors.extend(error_message(msg));
+        }
+        assert!(errors[..2].iter().all(|msg| msg != "Too many requests"));
+        assert_eq!("Too many requests", errors[2]);
+
+        // a frame over json_limit closes the session
+        let (mut ws, _) = connect().await.unwrap();
+        ws.send(Message::text("x".repeat(2000))).await.unwrap();
+        loop {
+            match ws.next().await {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(msg)) => assert!(error_message(msg).is_none()),
+            }
+        }
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn chat() {
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            string_max: 16,
+            chats_per_min: 4,
+            ..Default::default()
+        }));
+        let seat = {
+            let mut room = new_room();
+            let seat = room.join(None).unwrap();
+            app_state.rooms.write().unwrap().insert(0, room);
+            seat
+        };
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(get_room_id_chat)
+                .service(post_room_id_chat)
+                .service(post_room_id_player_chat),
+        )
+        .await;
+        let post = |uri: &str, body: jsif::ChatBody| {
+            TestRequest::post().uri(uri).set_json(body).to_request()
+        };
+        let text = |s: &str| jsif::ChatBody::Text(s.to_string());
+
+        let uri = format!("/api/room/0/0/chat?token={}", seat.token);
+        let resp = call_service(&app, post(&uri, text("hi"))).await;
+        assert!(resp.status().is_success());
+        let resp = call_service(
+            &app,
+            post("/api/room/0/chat", jsif::ChatBody::Stamp(jsif::Stamp::Nice)),
+        )
+        .await;
+        assert!(resp.status().is_success());
+        // length and token
+        let resp = call_service(&app, post(&uri, text(&"x".repeat(17)))).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+        let resp = call_service(&app, post("/api/room/0/1/chat?token=x", text("hi"))).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+        // 4 per min
+        let resp = call_service(&app, post(&uri, text("hi"))).await;
+        assert_eq!(http::StatusCode::TOO_MANY_REQUESTS, resp.status());
+
+        // with the game events
+        let req = TestRequest::get().uri("/api/room/0/chat").to_request();
+        let list: jsif::EventList = call_and_read_body_json(&app, req).await;
+        assert_eq!(2, list.0.len());
+        assert!(matches!(
+            &list.0[0].event,
+            jsif::GameEvent::Chat { player: Some(0), body } if *body == text("hi")
+        ));
+        assert!(matches!(
+            &list.0[1].event,
+            jsif::GameEvent::Chat {
+                player: None,
+                body: jsif::ChatBody::Stamp(jsif::Stamp::Nice)
+            }
+        ));
+        let uri = format!("/api/room/0/chat?since={}", list.0[0].seq);
+        let req = TestRequest::get().uri(&uri).to_request();
+        let list: jsif::EventList = call_and_read_body_json(&app, req).await;
+        assert_eq!(1, list.0.len());
+        let room = &app_state.rooms.read().unwrap()[&0];
+        assert_eq!(2, *room.update.borrow());
+    }
+
+    #[actix_web::test]
+    async fn admin() {
+        // disabled by default
+        assert!(check_admin(&Default::default(), "").is_err());
+
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            admin_token: Some("admin".to_string()),
+            ..Default::default()
+        }));
+        let seat = {
+            let mut room = new_room();
+            let seat = room.join(None).unwrap();
+            app_state.rooms.write().unwrap().insert(0, room);
+            seat
+        };
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(get_admin_rooms)
+                .service(get_admin_room_id)
+                .service(post_admin_room_id_end)
+                .service(post_admin_room_id_player_kick)
+                .service(delete_admin_room_id),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri("/api/admin/room?token=x")
+            .to_request();
+        assert_eq!(
+            http::StatusCode::FORBIDDEN,
+            call_service(&app, req).await.status()
+        );
+
+        let req = TestRequest::get()
+            .uri("/api/admin/room?token=admin")
+            .to_request();
+        let list: Vec<jsif::AdminRoom> = call_and_read_body_json(&app, req).await;
+        assert_eq!(1, list.len());
+        assert_eq!(jsif::GamePhase::WaitAction, list[0].phase);
+        assert_eq!(vec![true, false, false, false], list[0].room.seats);
+
+        // with the wall
+        let req = TestRequest::get()
+            .uri("/api/admin/room/0?token=admin")
+            .to_request();
+        let state: serde_json::Value = call_and_read_body_json(&app, req).await;
+        assert!(!state["internal"]["yama"].as_array().unwrap().is_empty());
+
+        let uri = "/api/admin/room/0/end?token=admin&draw=false";
+        let resp = call_service(&app, TestRequest::post().uri(uri).to_request()).await;
+        assert!(resp.status().is_success());
+        assert_eq!(
+            jsif::GamePhase::ShowResult,
+            app_state.rooms.read().unwrap()[&0].game.phase()
+        );
+        // not in a round
+        let resp = call_service(&app, TestRequest::post().uri(uri).to_request()).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+        let uri = format!("/api/admin/room/0/{}/kick?token=admin", seat.player);
+        let resp = call_service(&app, TestRequest::post().uri(&uri).to_request()).await;
+        assert!(resp.status().is_success());
+        assert!(app_state.rooms.read().unwrap()[&0]
+            .check_token(seat.player, &seat.token)
+            .is_err());
+        // already free
+        let resp = call_service(&app, TestRequest::post().uri(&uri).to_request()).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+        let uri = "/api/admin/room/0?token=admin";
+        let resp = call_service(&app, TestRequest::delete().uri(uri).to_request()).await;
+        assert!(resp.status().is_success());
+        assert!(app_state.rooms.read().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn metrics() {
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        app_state.rooms.write().unwrap().insert(0, new_room());
+        let app = init_service(App::new().app_data(app_state.clone()).service(get_metrics)).await;
+
+        let req = TestRequest::get().uri("/api/metrics").to_request();
+        let body = actix_web::test::call_and_read_body(&app, req).await;
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains("\nplaysv_rooms 1\n"));
+        assert!(text.contains("\nplaysv_games{phase=\"WaitAction\"} 1\n"));
+        assert!(text.contains("\nplaysv_games{phase=\"GameResult\"} 0\n"));
+    }
+
+    #[actix_web::test]
+    async fn mjai_seat() {
+        use std::io::{BufRead, BufReader, Write};
+
+        // a bot with no decision
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            for _ in BufReader::new(stream).lines().map_while(Result::ok) {
+                writer.write_all(b"{\"type\":\"none\"}\n").unwrap();
+            }
+        });
+
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            mjai_bot: Some(mjai::BotTarget::Tcp(addr)),
+            ..Default::default()
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_room)
+                .service(post_room_id_mjai),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/api/room")
+            .set_json(jsif::CreateRoom {
+                comment: "test".to_string(),
+                rule: Default::default(),
+            })
+            .to_request();
+        let room: jsif::CreateRoomResult = call_and_read_body_json(&app, req).await;
+        let id = room.room.id;
+
+        let req = TestRequest::post()
+            .uri(&format!("/api/room/{id}/mjai?token=xxx"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+
+        let uri = format!("/api/room/{id}/mjai?token={}", room.owner_token);
+        let req = TestRequest::post().uri(&uri).to_request();
+        let seat: jsif::SeatToken = call_and_read_body_json(&app, req).await;
+        assert_eq!(0, seat.player);
+
+        // the parent bot discards by default
+        for _ in 0..100 {
+            let discarded = {
+                let rooms = app_state.rooms.read().unwrap();
+                let view = rooms[&id].game.get_view(0).unwrap();
+                !view.common.rivers[0].is_empty()
+            };
+            if discarded {
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("no discard by the bot");
+    }
+
+    #[actix_web::test]
+    async fn mjai_fallback() {
+        // a bot closing the connection at once
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        std::thread::spawn(move || drop(listener.accept().unwrap()));
+
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            mjai_bot: Some(mjai::BotTarget::Tcp(addr)),
+            ..Default::default()
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_room_id_mjai),
+        )
+        .await;
+        let room = new_room();
+        let uri = format!("/api/room/1/mjai?token={}", room.owner_token);
+        app_state.rooms.write().unwrap().insert(1, room);
+        let req = TestRequest::post().uri(&uri).to_request();
+        let seat: jsif::SeatToken = call_and_read_body_json(&app, req).await;
+        assert_eq!(0, seat.player);
+
+        // the built-in CPU discards instead
+        for _ in 0..100 {
+            let discarded = {
+                let rooms = app_state.rooms.read().unwrap();
+                let view = rooms[&1].game.get_view(0).unwrap();
+                !view.common.rivers[0].is_empty()
+            };
+            if discarded {
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("no discard by the CPU");
+    }
+
+    #[actix_web::test]
+    async fn mjai_restore() {
+        // the bot is not available after the restart
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        let mut room = new_room();
+        let seat = room.join(None).unwrap();
+        room.mjai[seat.player as usize] = true;
+        spawn_tasks(&app_state, 1, &room);
+        app_state.rooms.write().unwrap().insert(1, room);
+
+        // the seat is freed
+        for _ in 0..100 {
+            let free = {
+                let rooms = app_state.rooms.read().unwrap();
+                rooms[&1].seats[0].is_none() && !rooms[&1].mjai[0]
+            };
+            if free {
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the mjai seat is not freed");
+    }
+
+    #[actix_web::test]
+    async fn cpu_players() {
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_room)
+                .service(post_room_id_join),
+        )
+        .await;
+        let req = TestRequest::post()
+            .uri("/api/room")
+            .set_json(jsif::CreateRoom {
+                comment: "test".to_string(),
+                rule: jsif::RuleConfig {
+                    cpu_players: 3,
+                    ..Default::default()
+                },
+            })
+            .to_request();
+        let room: jsif::CreateRoomResult = call_and_read_body_json(&app, req).await;
+        assert_eq!(vec![false, true, true, true], room.room.seats);
+        let id = room.room.id;
+
+        let req = TestRequest::post()
+            .uri(&format!("/api/room/{id}/join"))
+            .to_request();
+        let seat: jsif::SeatToken = call_and_read_body_json(&app, req).await;
+        assert_eq!(0, seat.player);
+        let req = TestRequest::post()
+            .uri(&format!("/api/room/{id}/join"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+        // tsumogiri, then CPU players go around
+        {
+            let rooms = app_state.rooms.read().unwrap();
+            let room = &rooms[&id];
+            let len = room.game.get_view(0).unwrap().local.hands[0].len() as i32;
+            room.action(0, &seat.token, jsif::Action::Discard(len))
+                .unwrap();
+        }
+        for _ in 0..100 {
+            let done = {
+                let rooms = app_state.rooms.read().unwrap();
+                let view = rooms[&id].game.get_view(0).unwrap();
+                !view.local.actions.is_empty()
+            };
+            if done {
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("CPU players did not act");
+    }
+
+    #[actix_web::test]
+    async fn turn_timer() {
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        let rule = jsif::RuleConfig {
+            time_limit: 1,
+            ..Default::default()
+        };
+        let room = RoomState::new(1, mjgame::Game::new(rule).unwrap(), String::new());
+        spawn_tasks(&app_state, 1, &room);
+        app_state.rooms.write().unwrap().insert(1, room);
+
+        // tsumogiri after the limit without players
+        let start = Instant::now();
+        for _ in 0..100 {
+            let rivers = {
+                let rooms = app_state.rooms.read().unwrap();
+                rooms[&1].game.get_view(0).unwrap().common.rivers
+            };
+            if !rivers[0].is_empty() {
+                assert!(start.elapsed() >= Duration::from_millis(900));
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("no time out");
+    }
+
+    #[actix_web::test]
+    async fn queue() {
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            rooms_per_min: 0,
+            ..Default::default()
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(get_queue)
+                .service(post_queue)
+                .service(get_queue_ticket)
+                .service(delete_queue_ticket),
+        )
+        .await;
+        let enter = |preset: &str| {
+            TestRequest::post()
+                .uri("/api/queue")
+                .set_json(jsif::EnterQueue {
+                    preset: preset.to_string(),
+                    session: None,
+                })
+                .to_request()
+        };
+
+        let resp = call_service(&app, enter("unknown")).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+        // a table for every 4 players
+        let mut tickets = vec![];
+        for _ in 0..4 {
+            let ticket: jsif::QueueTicket = call_and_read_body_json(&app, enter("hanchan")).await;
+            tickets.push(ticket.ticket);
+        }
+        let req = TestRequest::get().uri("/api/queue").to_request();
+        let list: jsif::QueueList = call_and_read_body_json(&app, req).await;
+        assert!(list.0.iter().all(|p| p.waiting == 0));
+        for (i, ticket) in tickets.iter().enumerate() {
+            let req = TestRequest::get()
+                .uri(&format!("/api/queue/{ticket}"))
+                .to_request();
+            let status: jsif::QueueStatus = call_and_read_body_json(&app, req).await;
+            let jsif::QueueStatus::Matched { room, seat } = status else {
+                panic!("Not matched: {status:?}");
+            };
+            assert_eq!(i as u32, seat.player);
+            let rooms = app_state.rooms.read().unwrap();
+            assert!(rooms[&room].check_token(seat.player, &seat.token).is_ok());
+        }
+
+        // waiting until cancel
+        let ticket: jsif::QueueTicket = call_and_read_body_json(&app, enter("tonpuu")).await;
+        let uri = format!("/api/queue/{}", ticket.ticket);
+        let req = TestRequest::get().uri(&uri).to_request();
+        let status: jsif::QueueStatus = call_and_read_body_json(&app, req).await;
+        assert!(matches!(
+            status,
+            jsif::QueueStatus::Waiting {
+                waiting: 1,
+                player_count: 4
+            }
+        ));
+        let req = TestRequest::delete().uri(&uri).to_request();
+        assert!(call_service(&app, req).await.status().is_success());
+        let req = TestRequest::get().uri(&uri).to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+        assert_eq!(1, app_state.rooms.read().unwrap().len());
+
+        // filled with CPU players
+        let app_state = web::Data::new(AppState::new(RoomConfig {
+            queue_bot_fill: Some(Duration::ZERO),
+            ..Default::default()
+        }));
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_queue)
+                .service(get_queue_ticket),
+        )
+        .await;
+        let ticket: jsif::QueueTicket = call_and_read_body_json(&app, enter("sanma")).await;
+        let req = TestRequest::get()
+            .uri(&format!("/api/queue/{}", ticket.ticket))
+            .to_request();
+        let status: jsif::QueueStatus = call_and_read_body_json(&app, req).await;
+        let jsif::QueueStatus::Matched { room, seat } = status else {
+            panic!("Not matched: {status:?}");
+        };
+        assert_eq!(0, seat.player);
+        let rooms = app_state.rooms.read().unwrap();
+        assert_eq!(vec![true, true, true], rooms[&room].to_jsif(room).seats);
+        assert_eq!(2, rooms[&room].game.rule().cpu_players);
+    }
+
+    #[actix_web::test]
+    async fn accounts() {
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        app_state.rooms.write().unwrap().insert(0, new_room());
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_account)
+                .service(post_account_login)
+                .service(delete_account_login)
+                .service(get_accounts)
+                .service(get_account_name)
+                .service(get_account_name_history)
+                .service(get_player_name_stats)
+                .service(post_room_id_join),
+        )
+        .await;
+        let cred = |uri: &str, password: &str| {
+            TestRequest::post()
+                .uri(uri)
+                .set_json(jsif::Credentials {
+                    name: "alice".to_string(),
+                    password: password.to_string(),
+                })
+                .to_request()
+        };
+
+        let account: jsif::AccountInfo =
+            call_and_read_body_json(&app, cred("/api/account", "password")).await;
+        assert_eq!(account::RATING_START, account.rating);
+        let resp = call_service(&app, cred("/api/account", "password")).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+        let resp = call_service(&app, cred("/api/account/login", "passwore")).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+        let session: jsif::Session =
+            call_and_read_body_json(&app, cred("/api/account/login", "password")).await;
+
+        // seated as the account
+        let req = TestRequest::post()
+            .uri("/api/room/0/join?session=xxx")
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+        let req = TestRequest::post()
+            .uri(&format!("/api/room/0/join?session={}", session.session))
+            .to_request();
+        let seat: jsif::SeatToken = call_and_read_body_json(&app, req).await;
+        {
+            let rooms = app_state.rooms.read().unwrap();
+            let accounts = &rooms[&0].to_jsif(0).accounts;
+            assert_eq!(Some("alice"), accounts[seat.player as usize].as_deref());
+        }
+        // once in a room
+        let join = || {
+            TestRequest::post()
+                .uri(&format!("/api/room/0/join?session={}", session.session))
+                .to_request()
+        };
+        let resp = call_service(&app, join()).await;
+        assert_eq!(http::StatusCode::BAD_REQUEST, resp.status());
+
+        // logout
+        let uri = format!("/api/account/login?session={}", session.session);
+        let resp = call_service(&app, TestRequest::delete().uri(&uri).to_request()).await;
+        assert!(resp.status().is_success());
+        let resp = call_service(&app, join()).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+        let resp = call_service(&app, TestRequest::delete().uri(&uri).to_request()).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+
+        // rated at the end of a game with accounts in all seats (played by CPU)
+        let rule = jsif::RuleConfig {
+            cpu_players: 4,
+            round_max: 1,
+            ..Default::default()
+        };
+        let mut room = RoomState::new(1, mjgame::Game::new(rule).unwrap(), String::new());
+        for (p, name) in ["bob", "carol", "alice", "dave"].into_iter().enumerate() {
+            if name != "alice" {
+                let mut accounts = app_state.accounts.lock().unwrap();
+                accounts.register(name, "x".to_string()).unwrap();
+            }
+            room.accounts[p] = Some(name.to_string());
+        }
+        for seat in room.cpu_seats() {
+            actix_web::rt::spawn(run_cpu(app_state.clone(), 1, seat));
+        }
+        app_state.rooms.write().unwrap().insert(1, room);
+        actix_web::rt::spawn(run_record(app_state.clone(), 1));
+        for _ in 0..1000 {
+            let req = TestRequest::get()
+                .uri("/api/account/alice/history")
+                .to_request();
+            let history: jsif::RatingHistory = call_and_read_body_json(&app, req).await;
+            if let Some(change) = history.0.first() {
+                assert_eq!(1, change.room);
+                let req = TestRequest::get().uri("/api/account").to_request();
+                let list: jsif::AccountList = call_and_read_body_json(&app, req).await;
+                let alice = list.0.iter().find(|a| a.name == "alice").unwrap();
+                assert_eq!(1, alice.games);
+                assert_eq!(change.rating, alice.rating);
+                let req = TestRequest::get()
+                    .uri("/api/player/alice/stats")
+                    .to_request();
+                let stats: jsif::PlayerStats = call_and_read_body_json(&app, req).await;
+                assert_eq!(1, stats.games);
+                assert!(stats.rounds >= 4);
+                assert_eq!(change.rank as f64 + 1.0, stats.average_rank);
+                let req = TestRequest::get().uri("/api/player/eve/stats").to_request();
+                let resp = call_service(&app, req).await;
+                assert_eq!(http::StatusCode::NOT_FOUND, resp.status());
+                return;
+            }
+            actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("Not rated");
+    }
+
+    #[actix_web::test]
+    async fn join_and_view() {
+        let app_state = web::Data::new(AppState::new(Default::default()));
+        app_state.rooms.write().unwrap().insert(0, new_room());
+        let app = init_service(
+            App::new()
+                .app_data(app_state.clone())
+                .service(post_room_id_join)
+                .service(post_room_id_player_leave)
+                .service(get_room_id_player),
+        )
+        .await;
+
+        let req = TestRequest::post().uri("/api/room/0/join").to_request();
+        let seat: jsif::SeatToken = call_and_read_body_json(&app, req).await;
+        assert_eq!(0, seat.player);
+
+        let uri = format!("/api/room/0/0?token={}", seat.token);
+        let req = TestRequest::get().uri(&uri).to_request();
+        let resp = call_service(&app, req).await;
+        assert!(resp.status().is_success());
+
+        for uri in [
+            "/api/room/0/0",
+            "/api/room/0/1?token=",
+            "/api/room/0/0?token=x",
+        ] {
+            let req = TestRequest::get().uri(uri).to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+        }
+        let uri = format!("/api/room/0/1?token={}", seat.token);
+        let req = TestRequest::get().uri(&uri).to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+
+        let uri = format!("/api/room/0/0/leave?token={}", seat.token);
+        let req = TestRequest::post().uri(&uri).to_request();
+        let resp = call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        let uri = format!("/api/room/0/0?token={}", seat.token);
+        let req = TestRequest::get().uri(&uri).to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(http::StatusCode::FORBIDDEN, resp.status());
+    }
 }
 
 /*
@@ -0,0 +1,172 @@
+use crate::cpu;
+use crate::mjgame::Game;
+use anyhow::{bail, ensure, Context, Result};
+use game::jsif;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// far more than a game needs
+const ACTION_MAX: usize = 100_000;
+
+// statistics of CPU-only games
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub games: u64,
+    pub rounds: u64,
+    // for each seat
+    pub points: Vec<i64>,
+    pub wins: Vec<u64>,
+    pub deal_ins: Vec<u64>,
+    // rounds without winner
+    pub draws: u64,
+    pub aborts: u64,
+    // yaku name -> count
+    pub yaku: BTreeMap<String, u64>,
+}
+
+impl Stats {
+    fn new(player_count: u32) -> Self {
+        let count = player_count as usize;
+        Self {
+            points: vec![0; count],
+            wins: vec![0; count],
+            deal_ins: vec![0; count],
+            ..Default::default()
+        }
+    }
+
+    fn add_game(&mut self, game: &Game) -> Result<()> {
+        self.games += 1;
+        for e in game.get_events(0, 0)? {
+            match e.event {
+                jsif::GameEvent::RoundEnd { result, .. } => {
+                    self.rounds += 1;
+                    if result.reason.is_abort() {
+                        self.aborts += 1;
+                    } else if result.wins.is_empty() {
+                        self.draws += 1;
+                    }
+                    for win in &result.wins {
+                        self.wins[win.player as usize] += 1;
+                        if win.from != win.player {
+                            self.deal_ins[win.from as usize] += 1;
+                        }
+                        for name in &win.yaku_str {
+                            *self.yaku.entry(name.clone()).or_default() += 1;
+                        }
+                    }
+                }
+                jsif::GameEvent::GameEnd { result } => {
+                    for s in &result.standings {
+                        self.points[s.player as usize] += s.point as i64;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let games = self.games.max(1) as f64;
+        let rounds = self.rounds.max(1) as f64;
+        let percent = |n: u64| n as f64 * 100.0 / rounds;
+
+        writeln!(f, "Games: {}, Rounds: {}", self.games, self.rounds)?;
+        writeln!(f, "Seat     Point   Win%  Deal-in%")?;
+        for p in 0..self.points.len() {
+            writeln!(
+                f,
+                "{:<4}  {:>8.0}  {:>5.1}  {:>8.1}",
+                p,
+                self.points[p] as f64 / games,
+                percent(self.wins[p]),
+                percent(self.deal_ins[p]),
+            )?;
+        }
+        writeln!(f, "Draw: {:.1}%", percent(self.draws))?;
+        writeln!(f, "Abort: {:.1}%", percent(self.aborts))?;
+
+        let wins = self.wins.iter().sum::<u64>().max(1) as f64;
+        let mut yaku: Vec<_> = self.yaku.iter().collect();
+        yaku.sort_by(|a, b| b.1.cmp(a.1));
+        writeln!(f, "Yaku (per win)")?;
+        for (name, &n) in yaku {
+            writeln!(f, "{:>5.1}%  {}", n as f64 * 100.0 / wins, name)?;
+        }
+
+        Ok(())
+    }
+}
+
+// all seats are CPU players, seed + i for the i-th game
+pub fn simulate(rule: &jsif::RuleConfig, games: u64, seed: u64) -> Result<Stats> {
+    let rule = jsif::RuleConfig {
+        cpu_players: rule.player_count,
+        ..rule.clone()
+    };
+    let mut stats = Stats::new(rule.player_count);
+    for i in 0..games {
+        let seed = seed.wrapping_add(i);
+        // for a panic with RUST_LOG=debug (errors have the seed)
+        log::debug!(game = i, seed = seed; "Started the game");
+        let game = play(&rule, seed).with_context(|| format!("Game {} (seed {})", i, seed))?;
+        stats.add_game(&game)?;
+    }
+
+    Ok(stats)
+}
+
+// check invariants after each action
+fn play(rule: &jsif::RuleConfig, seed: u64) -> Result<Game> {
+    let game = Game::with_seed(rule.clone(), seed)?;
+    game.verify()?;
+    for _ in 0..ACTION_MAX {
+        if game.phase() == jsif::GamePhase::GameResult {
+            return Ok(game);
+        }
+        let mut done = false;
+        for p in 0..rule.player_count {
+            let view = game.get_view(p)?;
+            if let Some(action) = cpu::decide(&view, p) {
+                game.action(p, action)?;
+                game.verify()?;
+                done = true;
+            }
+        }
+        ensure!(done, "No action in {:?}", game.phase());
+    }
+
+    bail!("Too many actions")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        for player_count in [3, 4] {
+            let rule = jsif::RuleConfig {
+                player_count,
+                round_max: 1,
+                uma: if player_count == 3 {
+                    vec![15000, 0, -15000]
+                } else {
+                    vec![20000, 10000, -10000, -20000]
+                },
+                ..Default::default()
+            };
+            let stats = simulate(&rule, 1, 1).unwrap();
+            assert_eq!(1, stats.games);
+            assert!(stats.rounds >= player_count as u64);
+            let points: i64 = stats.points.iter().sum();
+            assert_eq!(rule.start_point as i64 * player_count as i64, points);
+
+            assert_eq!(stats, simulate(&rule, 1, 1).unwrap());
+        }
+    }
+}
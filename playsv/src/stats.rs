@@ -0,0 +1,245 @@
+use game::jsif;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// counters from the event logs of finished games, saved with the account
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatCounts {
+    pub games: u32,
+    // count for each rank (0 origin)
+    pub ranks: Vec<u32>,
+    pub rounds: u32,
+    pub wins: u32,
+    // hand values without hon and deposit
+    pub win_points: u64,
+    pub deal_ins: u32,
+    pub reaches: u32,
+    // rounds with Chi, Pon or OpenKan
+    pub calls: u32,
+    // yaku name -> win count
+    pub yaku: BTreeMap<String, u32>,
+}
+
+fn ratio(count: impl Into<f64>, total: impl Into<f64>) -> f64 {
+    let total = total.into();
+    if total > 0.0 {
+        count.into() / total
+    } else {
+        0.0
+    }
+}
+
+impl StatCounts {
+    // hidden pais are not used (any player's event list is ok)
+    pub fn add_game(&mut self, events: &[jsif::Event], player: u32) {
+        let mut called = false;
+        for e in events {
+            match &e.event {
+                jsif::GameEvent::RoundStart { .. } => {
+                    self.rounds += 1;
+                    called = false;
+                }
+                jsif::GameEvent::Discard {
+                    player: p,
+                    reach: true,
+                    ..
+                } if *p == player => self.reaches += 1,
+                jsif::GameEvent::Call {
+                    player: p, meld, ..
+                } if *p == player => {
+                    let open = matches!(
+                        meld.mtype,
+                        jsif::MeldType::Chi | jsif::MeldType::Pon | jsif::MeldType::OpenKan
+                    );
+                    if open && !called {
+                        self.calls += 1;
+                        called = true;
+                    }
+                }
+                jsif::GameEvent::RoundEnd { result, .. } => {
+                    for win in result.wins.iter().filter(|w| w.player == player) {
+                        self.wins += 1;
+                        self.win_points += win.point.max(0) as u64;
+                        for yaku in &win.yaku_str {
+                            *self.yaku.entry(yaku.clone()).or_default() += 1;
+                        }
+                    }
+                    // once for double ron
+                    if result
+                        .wins
+                        .iter()
+                        .any(|w| w.from == player && w.player != player)
+                    {
+                        self.deal_ins += 1;
+                    }
+                }
+                jsif::GameEvent::GameEnd { result } => {
+                    let standing = result.standings.iter().find(|s| s.player == player);
+                    if let Some(s) = standing {
+                        let rank = s.rank as usize;
+                        if self.ranks.len() <= rank {
+                            self.ranks.resize(rank + 1, 0);
+                        }
+                        self.ranks[rank] += 1;
+                        self.games += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn to_jsif(&self, name: &str) -> jsif::PlayerStats {
+        let rank_sum: u32 = self
+            .ranks
+            .iter()
+            .enumerate()
+            .map(|(rank, count)| (rank as u32 + 1) * count)
+            .sum();
+        let mut yaku: Vec<(String, u32)> = self
+            .yaku
+            .iter()
+            .map(|(name, &count)| (name.clone(), count))
+            .collect();
+        // most frequent first
+        yaku.sort_by_key(|y| std::cmp::Reverse(y.1));
+
+        jsif::PlayerStats {
+            name: name.to_string(),
+            games: self.games,
+            rounds: self.rounds,
+            win_rate: ratio(self.wins, self.rounds),
+            deal_in_rate: ratio(self.deal_ins, self.rounds),
+            reach_rate: ratio(self.reaches, self.rounds),
+            call_rate: ratio(self.calls, self.rounds),
+            average_win: ratio(self.win_points as f64, self.wins),
+            average_rank: ratio(rank_sum, self.games),
+            ranks: self.ranks.clone(),
+            yaku,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_start() -> jsif::GameEvent {
+        jsif::GameEvent::RoundStart {
+            wind: 0,
+            parent: 0,
+            hon: 0,
+            deposit: 0,
+            dora: 0,
+            points: vec![25000; 4],
+            hands: vec![vec![]; 4],
+            yama: vec![],
+            yama2: vec![],
+        }
+    }
+
+    fn round_end(reason: jsif::RoundEnd, wins: Vec<(u32, u32, i32)>) -> jsif::GameEvent {
+        let wins = wins
+            .into_iter()
+            .map(|(player, from, point)| jsif::WinResult {
+                player,
+                from,
+                pai: 0,
+                fan: 1,
+                fu: 30,
+                yakuman_count: 0,
+                yaku: 0,
+                yakuman: 0,
+                yaku_str: vec!["立直".to_string()],
+                point,
+            })
+            .collect();
+
+        jsif::GameEvent::RoundEnd {
+            result: jsif::RoundResult {
+                reason,
+                tenpai: vec![false; 4],
+                hands: vec![vec![]; 4],
+                deltas: vec![0; 4],
+                wins,
+                renchan: false,
+                hon: 0,
+                game_over: false,
+            },
+            ura: vec![],
+            points: vec![25000; 4],
+        }
+    }
+
+    #[test]
+    fn add_game() {
+        let call = |player, mtype| jsif::GameEvent::Call {
+            player,
+            pai: 0,
+            meld: jsif::Meld {
+                mtype,
+                pai: 0,
+                from: 0,
+                red: 0,
+            },
+        };
+        let events: Vec<jsif::Event> = [
+            // reach and win by tsumo
+            round_start(),
+            jsif::GameEvent::Discard {
+                player: 1,
+                pai: 0,
+                tsumogiri: false,
+                reach: true,
+            },
+            round_end(jsif::RoundEnd::Tsumo, vec![(1, 1, 8000)]),
+            // pon twice, deal in to double ron
+            round_start(),
+            call(1, jsif::MeldType::Pon),
+            call(1, jsif::MeldType::Pon),
+            round_end(jsif::RoundEnd::Ron, vec![(0, 1, 1000), (2, 1, 2000)]),
+            // blind kan is not a call, draw
+            round_start(),
+            call(1, jsif::MeldType::BlindKan),
+            round_end(jsif::RoundEnd::ExhaustiveDraw, vec![]),
+            // win by ron
+            round_start(),
+            round_end(jsif::RoundEnd::Ron, vec![(1, 3, 4000)]),
+            jsif::GameEvent::GameEnd {
+                result: jsif::FinalResult {
+                    standings: vec![jsif::Standing {
+                        player: 1,
+                        rank: 1,
+                        point: 30000,
+                        score: 10000,
+                    }],
+                },
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, event)| jsif::Event {
+            seq: i as u64 + 1,
+            event,
+        })
+        .collect();
+
+        let mut counts = StatCounts::default();
+        counts.add_game(&events, 1);
+        let stats = counts.to_jsif("alice");
+        assert_eq!((1, 4), (stats.games, stats.rounds));
+        assert_eq!(0.5, stats.win_rate);
+        assert_eq!(0.25, stats.deal_in_rate);
+        assert_eq!(0.25, stats.reach_rate);
+        assert_eq!(0.25, stats.call_rate);
+        assert_eq!(6000.0, stats.average_win);
+        assert_eq!(2.0, stats.average_rank);
+        assert_eq!(vec![0, 1], stats.ranks);
+        assert_eq!(vec![("立直".to_string(), 2)], stats.yaku);
+
+        // empty
+        let stats = StatCounts::default().to_jsif("bob");
+        assert_eq!((0.0, 0.0), (stats.win_rate, stats.average_rank));
+    }
+}
@@ -0,0 +1,366 @@
+use game::mjsys::yaku::{Yaku, Yakuman};
+use game::{jsif, mjsys};
+use serde_json::{json, Value};
+
+/*
+tenhou.net/6 JSON log
+
+log: for each round
+[
+    [kyoku, hon, deposit], [points], [dora], [ura],
+    hand0, draws0, discards0, hand1, draws1, discards1, ...,
+    ["和了", [deltas], [who, from, pao, "point", "yaku(fan)", ...]] or ["流局", [deltas]]
+]
+pai: 11-19 (m), 21-29 (p), 31-39 (s), 41-47 (z), 51-53 (red 5m/5p/5s)
+draws: pai, or call (c: chi, p: pon, m: open kan) marked at the called position
+discards: pai, 60 (tsumogiri), r + pai (reach), 0 (after open kan),
+          k (added kan) or a (blind kan)
+*/
+
+const TSUMOGIRI: i32 = 60;
+
+fn to_tenhou_pai(pai: i32) -> i32 {
+    let (kind, num) = mjsys::decode(jsif::pai_code(pai) as u8);
+    if jsif::is_red(pai) {
+        50 + kind as i32 + 1
+    } else {
+        (kind as i32 + 1) * 10 + num as i32
+    }
+}
+
+// call string with the marker before the called pai
+// the position is by the relative seat of the called player
+fn call_string(mark: char, called: i32, own: &[i32], rel: u32) -> String {
+    let own: Vec<String> = own.iter().map(|&x| to_tenhou_pai(x).to_string()).collect();
+    let called = format!("{mark}{}", to_tenhou_pai(called));
+    // left (kamicha), across (toimen), right (shimocha)
+    let pos = match rel {
+        3 => 0,
+        2 => 1,
+        _ => own.len(),
+    };
+    let mut list = own;
+    list.insert(pos, called);
+
+    list.concat()
+}
+
+// pais of the meld except for the called (or added) pai
+pub fn meld_pais(meld: &jsif::Meld, called: i32, count: usize) -> Vec<i32> {
+    let mut red = meld.red - jsif::is_red(called) as u32;
+    let code = jsif::pai_code(called);
+    let mut pais = vec![];
+    let codes: Vec<i32> = match meld.mtype {
+        jsif::MeldType::Chi => (meld.pai..meld.pai + 3).filter(|&x| x != code).collect(),
+        _ => vec![meld.pai; count],
+    };
+    for x in codes {
+        if red > 0 && mjsys::decode(x as u8).1 == 5 {
+            red -= 1;
+            pais.push(x | jsif::PAI_RED);
+        } else {
+            pais.push(x);
+        }
+    }
+
+    pais
+}
+
+fn point_string(win: &jsif::WinResult, parent: bool) -> String {
+    let point =
+        mjsys::calc_base_point_direct(win.yakuman_count, win.fan, win.fu, win.yaku, win.yakuman);
+    let value = if win.player != win.from {
+        win.point.to_string()
+    } else if parent {
+        format!("{}点∀", point.calc_point_p_tumo())
+    } else {
+        let (child, parent) = point.calc_point_c_tumo();
+        format!("{child}-{parent}")
+    };
+    let value = if value.ends_with('∀') {
+        value
+    } else {
+        format!("{value}点")
+    };
+
+    let limit = match win.fan {
+        _ if win.yakuman_count > 0 => "役満",
+        13.. => "数え役満",
+        11.. => "三倍満",
+        8.. => "倍満",
+        6.. => "跳満",
+        _ if point.base_point >= 2000 => "満貫",
+        _ => "",
+    };
+    if limit.is_empty() {
+        format!("{}符{}飜{value}", win.fu, win.fan)
+    } else {
+        format!("{limit}{value}")
+    }
+}
+
+fn yaku_strings(win: &jsif::WinResult) -> Vec<String> {
+    let mut result = vec![];
+    if win.yakuman_count > 0 {
+        for i in 0..u32::BITS {
+            let bit = 1 << i;
+            if win.yakuman & bit != 0 {
+                let name = Yakuman::to_japanese_list(bit)[0];
+                result.push(format!("{name}(役満)"));
+            }
+        }
+        return result;
+    }
+
+    let mut fan = 0;
+    for i in 0..u64::BITS {
+        let bit = 1 << i;
+        if win.yaku & bit != 0 {
+            let name = Yaku::to_japanese_list(bit)[0];
+            let n = Yaku::fan_sum(bit);
+            fan += n;
+            result.push(format!("{name}({n}飜)"));
+        }
+    }
+    // dora, ura and red
+    if win.fan > fan {
+        result.push(format!("ドラ({}飜)", win.fan - fan));
+    }
+
+    result
+}
+
+fn result_entry(result: &jsif::RoundResult, parent: u32) -> Value {
+    let name = match result.reason {
+        jsif::RoundEnd::Tsumo | jsif::RoundEnd::Ron => "和了",
+        jsif::RoundEnd::ExhaustiveDraw => "流局",
+        jsif::RoundEnd::KyushuKyuhai => "九種九牌",
+        jsif::RoundEnd::FourWinds => "四風連打",
+        jsif::RoundEnd::FourReach => "四家立直",
+        jsif::RoundEnd::FourKans => "四槓散了",
+        jsif::RoundEnd::TripleRon => "三家和了",
+        jsif::RoundEnd::Canceled => "流局",
+    };
+    let mut entry = vec![json!(name)];
+    if result.wins.is_empty() {
+        entry.push(json!(result.deltas));
+        return Value::Array(entry);
+    }
+
+    // hon and deposit go to the first winner
+    let mut first = result.deltas.clone();
+    let mut rest = vec![];
+    for win in &result.wins[1..] {
+        let mut deltas = vec![0; result.deltas.len()];
+        deltas[win.player as usize] += win.point;
+        deltas[win.from as usize] -= win.point;
+        for (x, d) in first.iter_mut().zip(deltas.iter()) {
+            *x -= d;
+        }
+        rest.push(deltas);
+    }
+    for (win, deltas) in result.wins.iter().zip(std::iter::once(first).chain(rest)) {
+        let mut info = vec![
+            json!(win.player),
+            json!(win.from),
+            json!(win.player),
+            json!(point_string(win, win.player == parent)),
+        ];
+        info.extend(yaku_strings(win).into_iter().map(Value::from));
+        entry.push(json!(deltas));
+        entry.push(Value::Array(info));
+    }
+
+    Value::Array(entry)
+}
+
+// for each round
+#[derive(Default)]
+struct Round {
+    header: Vec<u32>,
+    points: Vec<i32>,
+    parent: u32,
+    dora: Vec<i32>,
+    ura: Vec<i32>,
+    hands: Vec<Vec<i32>>,
+    draws: Vec<Vec<Value>>,
+    discards: Vec<Vec<Value>>,
+    reach: Vec<bool>,
+    result: Value,
+}
+
+impl Round {
+    fn to_json(&self) -> Value {
+        let mut list = vec![
+            json!(self.header),
+            json!(self.points),
+            json!(self.dora),
+            json!(self.ura),
+        ];
+        for p in 0..self.hands.len() {
+            let hand: Vec<i32> = self.hands[p].iter().map(|&x| to_tenhou_pai(x)).collect();
+            list.push(json!(hand));
+            list.push(json!(self.draws[p]));
+            list.push(json!(self.discards[p]));
+        }
+        list.push(self.result.clone());
+
+        Value::Array(list)
+    }
+}
+
+// events of the whole game (with hidden information) to a tenhou log
+pub fn export(title: &str, rule: &jsif::RuleConfig, events: &[jsif::Event]) -> Value {
+    let count = rule.player_count;
+    let mut log = vec![];
+    let mut round = Round::default();
+    let mut sc = vec![];
+
+    for e in events {
+        match &e.event {
+            jsif::GameEvent::RoundStart {
+                wind,
+                parent,
+                hon,
+                deposit,
+                dora,
+                points,
+                hands,
+                ..
+            } => {
+                round = Round {
+                    header: vec![wind * count + parent, *hon, *deposit],
+                    points: points.clone(),
+                    parent: *parent,
+                    dora: vec![to_tenhou_pai(*dora)],
+                    hands: hands.clone(),
+                    draws: vec![vec![]; count as usize],
+                    discards: vec![vec![]; count as usize],
+                    reach: vec![false; count as usize],
+                    ..Default::default()
+                };
+            }
+            jsif::GameEvent::Draw { player, pai, .. } => {
+                round.draws[*player as usize].push(json!(to_tenhou_pai(*pai)));
+            }
+            jsif::GameEvent::Discard {
+                player,
+                pai,
+                tsumogiri,
+                reach,
+            } => {
+                let p = *player as usize;
+                let pai = if *tsumogiri {
+                    TSUMOGIRI
+                } else {
+                    to_tenhou_pai(*pai)
+                };
+                round.discards[p].push(if *reach {
+                    round.reach[p] = true;
+                    json!(format!("r{pai}"))
+                } else {
+                    json!(pai)
+                });
+            }
+            jsif::GameEvent::ReachAccepted { .. } => {}
+            jsif::GameEvent::Call { player, pai, meld } => {
+                let p = *player as usize;
+                let rel = (meld.from + count - player) % count;
+                match meld.mtype {
+                    jsif::MeldType::Chi => {
+                        let s = call_string('c', *pai, &meld_pais(meld, *pai, 2), 3);
+                        round.draws[p].push(json!(s));
+                    }
+                    jsif::MeldType::Pon => {
+                        let s = call_string('p', *pai, &meld_pais(meld, *pai, 2), rel);
+                        round.draws[p].push(json!(s));
+                    }
+                    jsif::MeldType::OpenKan => {
+                        let s = call_string('m', *pai, &meld_pais(meld, *pai, 3), rel);
+                        round.draws[p].push(json!(s));
+                        round.discards[p].push(json!(0));
+                    }
+                    jsif::MeldType::AddedKan => {
+                        let s = call_string('k', *pai, &meld_pais(meld, *pai, 3), rel);
+                        round.discards[p].push(json!(s));
+                    }
+                    jsif::MeldType::BlindKan => {
+                        let pais = meld_pais(meld, *pai, 4);
+                        let s = call_string('a', pais[3], &pais[0..3], 1);
+                        round.discards[p].push(json!(s));
+                    }
+                }
+            }
+            jsif::GameEvent::Dora { indicator } => {
+                round.dora.push(to_tenhou_pai(*indicator));
+            }
+            jsif::GameEvent::RoundEnd { result, ura, .. } => {
+                // only if a reach player wins
+                let reach_win = result.wins.iter().any(|w| round.reach[w.player as usize]);
+                if reach_win {
+                    round.ura = ura.iter().map(|&x| to_tenhou_pai(x)).collect();
+                }
+                round.result = result_entry(result, round.parent);
+                log.push(round.to_json());
+            }
+            jsif::GameEvent::Chat { .. } => {}
+            jsif::GameEvent::GameEnd { result } => {
+                let mut standings = result.standings.clone();
+                standings.sort_by_key(|s| s.player);
+                for s in standings {
+                    sc.push(json!(s.point));
+                    sc.push(json!(s.score as f64 / 1000.0));
+                }
+            }
+        }
+    }
+
+    let mut disp = String::from("般");
+    disp.push(if rule.round_max >= 2 { '南' } else { '東' });
+    if rule.open_tanyao {
+        disp.push('喰');
+    }
+    if rule.red_five {
+        disp.push('赤');
+    }
+    let aka = rule.red_five as u32;
+    let names: Vec<String> = (0..count).map(|p| format!("Player{p}")).collect();
+
+    json!({
+        "title": [title, ""],
+        "name": names,
+        "rule": {"disp": disp, "aka": aka, "aka51": aka, "aka52": aka, "aka53": aka},
+        "log": log,
+        "sc": sc,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pai_and_call() {
+        let red = |x: i32| x | jsif::PAI_RED;
+        assert_eq!(11, to_tenhou_pai(0));
+        assert_eq!(29, to_tenhou_pai(17));
+        assert_eq!(47, to_tenhou_pai(33));
+        assert_eq!(51, to_tenhou_pai(red(4)));
+        assert_eq!(53, to_tenhou_pai(red(22)));
+
+        // pon of 1m from left, across and right
+        assert_eq!("p111111", call_string('p', 0, &[0, 0], 3));
+        assert_eq!("11p1111", call_string('p', 0, &[0, 0], 2));
+        assert_eq!("1111p11", call_string('p', 0, &[0, 0], 1));
+
+        // chi 3-4-5p with red 5p
+        let meld = jsif::Meld {
+            mtype: jsif::MeldType::Chi,
+            pai: 11,
+            from: 3,
+            red: 1,
+        };
+        assert_eq!(vec![12, red(13)], meld_pais(&meld, 11, 2));
+        assert_eq!("c232452", call_string('c', 11, &meld_pais(&meld, 11, 2), 3));
+    }
+}
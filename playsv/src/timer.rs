@@ -0,0 +1,111 @@
+use game::jsif;
+use std::time::{Duration, Instant};
+
+// time_limit for each decision, then the extra time bank of the player
+// a decision is keyed by the seq of the last game event
+pub struct TurnTimer {
+    limit: Duration,
+    // the rest of the bank for each player
+    extra: Vec<Duration>,
+    // (decision key, start) while the player has actions
+    waiting: Vec<Option<(u64, Instant)>>,
+}
+
+impl TurnTimer {
+    // None if unlimited
+    pub fn new(rule: &jsif::RuleConfig) -> Option<Self> {
+        if rule.time_limit == 0 {
+            return None;
+        }
+        let count = rule.player_count as usize;
+
+        Some(Self {
+            limit: Duration::from_secs(rule.time_limit as u64),
+            extra: vec![Duration::from_secs(rule.time_extra as u64); count],
+            waiting: vec![None; count],
+        })
+    }
+
+    // the current decision and players with actions
+    // time over the limit is taken from the bank when a wait ends
+    pub fn update(&mut self, key: u64, pending: &[bool], now: Instant) {
+        for (p, &pending) in pending.iter().enumerate() {
+            if let Some((k, start)) = self.waiting[p] {
+                if k != key || !pending {
+                    let over = now.saturating_duration_since(start + self.limit);
+                    self.extra[p] = self.extra[p].saturating_sub(over);
+                    self.waiting[p] = None;
+                }
+            }
+            if pending && self.waiting[p].is_none() {
+                self.waiting[p] = Some((key, now));
+            }
+        }
+    }
+
+    fn deadlines(&self) -> impl Iterator<Item = (u32, Instant)> + '_ {
+        self.waiting
+            .iter()
+            .enumerate()
+            .filter_map(|(p, w)| w.map(|(_, start)| (p as u32, start + self.limit + self.extra[p])))
+    }
+
+    // the earliest time out (None if no one is waited)
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadlines().map(|(_, t)| t).min()
+    }
+
+    pub fn expired(&self, now: Instant) -> Vec<u32> {
+        self.deadlines()
+            .filter(|&(_, t)| t <= now)
+            .map(|(p, _)| p)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_timer() {
+        let secs = Duration::from_secs;
+        let unlimited = jsif::RuleConfig::default();
+        assert!(TurnTimer::new(&unlimited).is_none());
+
+        let rule = jsif::RuleConfig {
+            time_limit: 10,
+            time_extra: 20,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let mut timer = TurnTimer::new(&rule).unwrap();
+        timer.update(1, &[false; 4], start);
+        assert_eq!(None, timer.deadline());
+
+        // limit + bank
+        timer.update(2, &[true, false, false, false], start);
+        assert_eq!(Some(start + secs(30)), timer.deadline());
+        assert!(timer.expired(start + secs(29)).is_empty());
+        assert_eq!(vec![0], timer.expired(start + secs(30)));
+        // continued (chat does not change the key)
+        timer.update(2, &[true, false, false, false], start + secs(5));
+        assert_eq!(Some(start + secs(30)), timer.deadline());
+
+        // 15 secs taken from the bank
+        timer.update(3, &[false, true, true, false], start + secs(25));
+        assert_eq!(Some(start + secs(55)), timer.deadline());
+        // player 1 acted in time, player 2 is still waited
+        timer.update(3, &[false, false, true, false], start + secs(30));
+        assert_eq!(Some(start + secs(55)), timer.deadline());
+        assert_eq!(vec![2], timer.expired(start + secs(55)));
+
+        // the bank of player 0 is 5 secs
+        let t = start + secs(100);
+        timer.update(4, &[true, false, false, false], t);
+        assert_eq!(Some(t + secs(15)), timer.deadline());
+        // the bank is empty after time out
+        timer.update(5, &[true, false, false, false], t + secs(15));
+        assert_eq!(Some(t + secs(25)), timer.deadline());
+    }
+}